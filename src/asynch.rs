@@ -0,0 +1,468 @@
+//! Async driver, built on `embedded-hal-async`. Requires the `async` feature.
+
+use embedded_hal::{digital::OutputPin, i2c::Error as _};
+use embedded_hal_async::{delay::DelayNs, i2c::{ErrorType, I2c, Operation}};
+
+use crate::{device::{check_range, pages}, id_page::{is_lock_nack, ID_LOCK_ADDRESS, ID_LOCK_DATA, ID_PAGE_ADDRESS}, is_nack, readback::Comparison, Chip, EPins, Error, IdPage, NoWriteControl, WritePolling};
+
+/// Async M24Cxx Driver, generic over the geometry of the part (see [`crate::chip`])
+pub struct M24Cxx<I2C, C, WC = NoWriteControl> {
+  inner: crate::M24Cxx<I2C, C, WC>
+}
+
+aliases!(M24Cxx);
+
+impl<I2C, C: Chip> M24Cxx<I2C, C, NoWriteControl> {
+  /// Size of the memory array, in bytes
+  pub const CAPACITY: usize = C::CAPACITY;
+  /// Size of a single write page, in bytes
+  pub const PAGE_SIZE: usize = C::PAGE_SIZE;
+
+  /// Create a new instance of the driver. See [`crate::M24Cxx::new`].
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::asynch::M24C64;
+  /// # fn example(i2c: impl embedded_hal_async::i2c::I2c) {
+  ///
+  /// let eeprom = M24C64::new(i2c, 0);
+  /// # }
+  /// ```
+  pub fn new(i2c: I2C, e_addr: u8) -> Self {
+    Self { inner: crate::M24Cxx::new(i2c, e_addr) }
+  }
+
+  /// Create a new instance of the driver. See [`crate::M24Cxx::try_new`].
+  pub fn try_new(i2c: I2C, e_addr: u8) -> Result<Self, Error<I2C::Error>> where I2C: ErrorType {
+    crate::M24Cxx::try_new(i2c, e_addr).map(|inner| Self { inner })
+  }
+
+  /// Create a new instance of the driver from the levels of its E pins. See [`crate::M24Cxx::with_e_pins`].
+  pub fn with_e_pins(i2c: I2C, e_pins: EPins) -> Self {
+    Self { inner: crate::M24Cxx::with_e_pins(i2c, e_pins) }
+  }
+
+  /// Create a new instance of the driver from the levels of its E pins. See [`crate::M24Cxx::try_with_e_pins`].
+  pub fn try_with_e_pins(i2c: I2C, e_pins: EPins) -> Result<Self, Error<I2C::Error>> where I2C: ErrorType {
+    crate::M24Cxx::try_with_e_pins(i2c, e_pins).map(|inner| Self { inner })
+  }
+
+  /// Attach the Write Control (WC) pin. See [`crate::M24Cxx::with_write_control`].
+  pub fn with_write_control<WC: OutputPin>(self, wc: WC) -> M24Cxx<I2C, C, WC> {
+    M24Cxx { inner: self.inner.with_write_control(wc) }
+  }
+}
+
+impl<I2C, C: Chip, WC> M24Cxx<I2C, C, WC> {
+  /// Set how the completion of the internal write cycle is polled for. See [`WritePolling`].
+  pub fn with_write_polling(self, polling: WritePolling) -> Self {
+    Self { inner: self.inner.with_write_polling(polling) }
+  }
+
+  /// Set how the completion of the internal write cycle is polled for
+  pub fn set_write_polling(&mut self, polling: WritePolling) {
+    self.inner.set_write_polling(polling)
+  }
+
+  /// How the completion of the internal write cycle is polled for
+  pub fn write_polling(&self) -> WritePolling {
+    self.inner.write_polling()
+  }
+
+  /// Limit the number of bytes read in a single I2C transfer. See [`crate::M24Cxx::with_max_read_len`].
+  pub fn with_max_read_len(self, max_read_len: usize) -> Self {
+    Self { inner: self.inner.with_max_read_len(max_read_len) }
+  }
+
+  /// Limit the number of bytes read in a single I2C transfer, or `None` for no limit
+  pub fn set_max_read_len(&mut self, max_read_len: Option<usize>) {
+    self.inner.set_max_read_len(max_read_len)
+  }
+
+  /// The effective 7-bit I2C address used to talk to the device
+  pub fn address(&self) -> u8 {
+    self.inner.address()
+  }
+
+  /// Mutable access to the I2C interface. See [`crate::M24Cxx::i2c_mut`].
+  pub fn i2c_mut(&mut self) -> &mut I2C {
+    self.inner.i2c_mut()
+  }
+
+  /// Destroy the driver, giving back the I2C interface
+  pub fn release(self) -> I2C {
+    self.inner.release()
+  }
+
+  /// Destroy the driver, giving back the I2C interface and the Write Control pin
+  pub fn release_parts(self) -> (I2C, WC) {
+    self.inner.release_parts()
+  }
+}
+
+impl<I2C, C, WC> M24Cxx<I2C, C, WC>
+where
+  I2C: I2c,
+  C: Chip,
+  WC: OutputPin
+{
+  /// Release the Write Control pin, run `f`, and drive it high again afterwards, even if `f` fails.
+  /// See [`crate::M24Cxx::with_write_enabled`].
+  pub async fn with_write_enabled<T, E>(&mut self, f: impl AsyncFnOnce(&mut Self) -> Result<T, Error<E>>) -> Result<T, Error<E>> {
+    if self.inner.write_enabled {
+      return f(self).await;
+    }
+
+    self.inner.enable_writes()?;
+    let result = f(self).await;
+    let restore = self.inner.disable_writes();
+
+    let value = result?;
+    restore?;
+    Ok(value)
+  }
+
+  async fn write_raw(&mut self, address: usize, bytes: &[u8], delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    let (device, cmd, start) = self.inner.encode_address(address);
+    self.write_cmd(device, &cmd[start..], bytes, delay).await
+  }
+
+  /// Send a write of `cmd` (the memory address) followed by `bytes` to `device`, and wait for the device to
+  /// finish its internal write cycle.
+  async fn write_cmd(&mut self, device: u8, cmd: &[u8], bytes: &[u8], delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    match self.inner.i2c.transaction(device, &mut [Operation::Write(cmd), Operation::Write(bytes)]).await {
+      Ok(_) => (),
+      Err(e) if is_nack(&e) && self.inner.write_pending => {
+        self.wait_write_cycle(delay).await?;
+        self.inner.i2c.transaction(device, &mut [Operation::Write(cmd), Operation::Write(bytes)]).await.map_err(Error::Bus)?;
+      },
+      Err(e) => return Err(Error::Bus(e))
+    }
+
+    self.wait_write_cycle(delay).await
+  }
+
+  /// Wait for the device to finish its internal write cycle. See [`crate::M24Cxx::wait_write_cycle`].
+  pub async fn wait_write_cycle(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    let polling = self.inner.polling;
+    let mut elapsed = 0;
+    loop {
+      match self.inner.i2c.write(self.address(), &[]).await {
+        Ok(_) => {
+          self.inner.write_pending = false;
+          return Ok(());
+        },
+        Err(e) if !is_nack(&e) => return Err(Error::Bus(e)),
+        Err(_) if elapsed >= polling.timeout_us => {
+          self.inner.write_pending = true;
+          return Err(Error::WriteTimeout);
+        },
+        Err(_) => ()
+      }
+      delay.delay_us(polling.interval_us).await;
+      elapsed = elapsed.saturating_add(polling.interval_us.max(1));
+    }
+  }
+
+  async fn read_raw(&mut self, address: usize, bytes: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    let (device, cmd, start) = self.inner.encode_address(address);
+    self.inner.i2c.write_read(device, &cmd[start..], bytes).await.map_err(Error::Bus)
+  }
+
+  async fn compare(&mut self, address: usize, data: &[u8]) -> Result<Option<(usize, usize)>, Error<I2C::Error>> {
+    let mut comparison = Comparison::new(data);
+    while let Some((offset, stored)) = comparison.next_read() {
+      self.read(address + offset, stored).await?;
+      comparison.compare_read();
+    }
+    Ok(comparison.changed())
+  }
+
+  /// Write an arbitrary number of bytes into the EEPROM, starting at `address`.
+  /// This function will automatically paginate, and releases the Write Control pin (if any) for the duration
+  /// of the write. Once this returns `Ok`, the final write cycle has completed and the data is committed.
+  /// Returns [`Error::AddressOutOfRange`] without touching the bus if the range does not fit in the device.
+  pub async fn write(&mut self, address: usize, data: &[u8], delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    self.with_write_enabled(async |eeprom| {
+      for (i, page) in pages(C::PAGE_SIZE, address, data) {
+        eeprom.write_raw(i, page, delay).await?;
+      }
+      Ok(())
+    }).await
+  }
+
+  /// Write bytes into the EEPROM, skipping anything that already holds the same data.
+  /// See [`crate::M24Cxx::write_if_changed`].
+  pub async fn write_if_changed(&mut self, address: usize, data: &[u8], delay: &mut impl DelayNs) -> Result<usize, Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    self.with_write_enabled(async |eeprom| {
+      let mut programmed = 0;
+      for (i, page) in pages(C::PAGE_SIZE, address, data) {
+        if let Some((first, last)) = eeprom.compare(i, page).await? {
+          eeprom.write_raw(i + first, &page[first..=last], delay).await?;
+          programmed += 1;
+        }
+      }
+      Ok(programmed)
+    }).await
+  }
+
+  /// Write bytes into the EEPROM, reading each page back and rewriting it up to `retries` more times if it doesn't match.
+  /// See [`crate::M24Cxx::write_verified`].
+  pub async fn write_verified(&mut self, address: usize, data: &[u8], retries: u8, delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    self.with_write_enabled(async |eeprom| {
+      for (i, page) in pages(C::PAGE_SIZE, address, data) {
+        let mut attempts = 0;
+        eeprom.write_raw(i, page, delay).await?;
+        while let Some((first, last)) = eeprom.compare(i, page).await? {
+          if attempts == retries {
+            return Err(Error::VerifyFailed { address: i + first });
+          }
+          attempts += 1;
+          eeprom.write_raw(i + first, &page[first..=last], delay).await?;
+        }
+      }
+      Ok(())
+    }).await
+  }
+
+  /// Read an arbitrary number of bytes from the EEPROM, starting at `address`.
+  /// This is sent as a single sequential read unless limited by [`M24Cxx::with_max_read_len`].
+  /// Returns [`Error::AddressOutOfRange`] without touching the bus if the range does not fit in the device.
+  pub async fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    let mut i = 0;
+    while i < data.len() {
+      let len = self.inner.read_chunk_len(address + i, data.len() - i);
+      self.read_raw(address + i, &mut data[i..(i + len)]).await?;
+      i += len;
+    }
+    Ok(())
+  }
+
+  /// Read a single byte from `address` (random address read)
+  pub async fn read_byte(&mut self, address: usize) -> Result<u8, Error<I2C::Error>> {
+    let mut buf = [0u8];
+    self.read(address, &mut buf).await?;
+    Ok(buf[0])
+  }
+
+  /// Write a single byte to `address` (byte write)
+  pub async fn write_byte(&mut self, address: usize, value: u8, delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    self.write(address, &[value], delay).await
+  }
+
+  /// Read the byte at the device's internal address counter. See [`crate::M24Cxx::read_current`].
+  pub async fn read_current(&mut self) -> Result<u8, Error<I2C::Error>> {
+    let mut buf = [0u8];
+    self.read_next(&mut buf).await?;
+    Ok(buf[0])
+  }
+
+  /// Read sequentially from the device's internal address counter. See [`crate::M24Cxx::read_next`].
+  pub async fn read_next(&mut self, data: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    let address = self.address();
+    self.inner.i2c.read(address, data).await.map_err(Error::Bus)
+  }
+
+  /// Store a plain-old-data value at `address`. See [`crate::M24Cxx::store`].
+  #[cfg(feature = "bytemuck")]
+  pub async fn store<T: bytemuck::Pod>(&mut self, address: usize, value: &T, delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    self.write(address, bytemuck::bytes_of(value), delay).await
+  }
+
+  /// Load a plain-old-data value stored at `address`. See [`crate::M24Cxx::load`].
+  #[cfg(feature = "bytemuck")]
+  pub async fn load<T: bytemuck::Pod>(&mut self, address: usize) -> Result<T, Error<I2C::Error>> {
+    let mut value = T::zeroed();
+    self.read(address, bytemuck::bytes_of_mut(&mut value)).await?;
+    Ok(value)
+  }
+
+  /// Serialize `value` with postcard into `buf`, and store it at `address`. See [`crate::M24Cxx::store_serde`].
+  #[cfg(feature = "serde")]
+  pub async fn store_serde<T: serde::Serialize>(&mut self, address: usize, value: &T, buf: &mut [u8], delay: &mut impl DelayNs) -> Result<usize, Error<I2C::Error>> {
+    let encoded = postcard::to_slice(value, buf).map_err(|_| Error::Serialization)?;
+    self.write(address, encoded, delay).await?;
+    Ok(encoded.len())
+  }
+
+  /// Load a value stored at `address` with [`M24Cxx::store_serde`]. See [`crate::M24Cxx::load_serde`].
+  #[cfg(feature = "serde")]
+  pub async fn load_serde<T: serde::de::DeserializeOwned>(&mut self, address: usize, buf: &mut [u8]) -> Result<T, Error<I2C::Error>> {
+    let buf = crate::M24Cxx::<I2C, C, WC>::clamp_to_capacity(address, buf)?;
+    self.read(address, buf).await?;
+    postcard::from_bytes(buf).map_err(|_| Error::Serialization)
+  }
+}
+
+impl<I2C, C, WC> M24Cxx<I2C, C, WC>
+where
+  I2C: I2c,
+  C: IdPage,
+  WC: OutputPin
+{
+  /// The 7-bit I2C address of the Identification Page (`1011 E2 E1 E0`)
+  pub fn id_page_address(&self) -> u8 {
+    self.inner.id_page_address()
+  }
+
+  /// Read bytes from the Identification Page. See [`crate::M24Cxx::read_id_page`].
+  pub async fn read_id_page(&mut self, offset: usize, data: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    crate::M24Cxx::<I2C, C, WC>::check_id_range(offset, data.len())?;
+    let cmd = crate::M24Cxx::<I2C, C, WC>::id_page_cmd(offset);
+    self.inner.i2c.write_read(self.id_page_address(), &cmd, data).await.map_err(Error::Bus)
+  }
+
+  /// Write bytes into the Identification Page. See [`crate::M24Cxx::write_id_page`].
+  pub async fn write_id_page(&mut self, offset: usize, data: &[u8], delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    crate::M24Cxx::<I2C, C, WC>::check_id_range(offset, data.len())?;
+    if self.is_id_page_locked().await? {
+      return Err(Error::IdPageLocked);
+    }
+    let (device, cmd) = (self.id_page_address(), crate::M24Cxx::<I2C, C, WC>::id_page_cmd(offset));
+    self.with_write_enabled(async |eeprom| eeprom.write_cmd(device, &cmd, data, delay).await).await
+  }
+
+  /// Permanently lock the Identification Page, making it read-only. This cannot be undone.
+  pub async fn lock_id_page(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<I2C::Error>> {
+    let device = self.id_page_address();
+    self.with_write_enabled(async |eeprom| eeprom.write_cmd(device, &ID_LOCK_ADDRESS, &[ID_LOCK_DATA], delay).await).await
+  }
+
+  /// Check whether the Identification Page has been locked. See [`crate::M24Cxx::is_id_page_locked`].
+  pub async fn is_id_page_locked(&mut self) -> Result<bool, Error<I2C::Error>> {
+    let mut current = [0u8];
+    self.read_id_page(0, &mut current).await?;
+
+    let device = self.id_page_address();
+    self.with_write_enabled(async |eeprom| {
+      let mut buf = [0u8];
+      let result = eeprom.inner.i2c.transaction(device, &mut [
+        Operation::Write(&ID_PAGE_ADDRESS), Operation::Write(&current), Operation::Read(&mut buf)
+      ]).await;
+
+      match result {
+        Ok(_) => Ok(false),
+        Err(e) if is_lock_nack(e.kind()) => Ok(true),
+        Err(e) => Err(Error::Bus(e))
+      }
+    }).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use core::{future::Future, pin::pin, task::{Context, Poll, Waker}};
+
+  use embedded_hal::{delay, i2c::{self, ErrorKind, NoAcknowledgeSource}};
+  use embedded_hal_async::{delay::DelayNs, i2c::{ErrorType, I2c, Operation}};
+
+  use super::M24C64;
+  use crate::{sim::{self, NoDelay, SimError}, Error, WritePolling};
+
+  /// Runs a blocking I2C bus or delay as an async one
+  struct Async<T>(T);
+
+  impl<T: i2c::ErrorType> ErrorType for Async<T> {
+    type Error = T::Error;
+  }
+
+  impl<T: i2c::I2c> I2c for Async<T> {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+      self.0.transaction(address, operations)
+    }
+  }
+
+  impl<T: delay::DelayNs> DelayNs for Async<T> {
+    async fn delay_ns(&mut self, ns: u32) {
+      self.0.delay_ns(ns)
+    }
+  }
+
+  /// Run a future that never has to wait (the simulated device answers straight away) to completion
+  fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+      if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+        return output;
+      }
+    }
+  }
+
+  #[test]
+  fn write_read_round_trip() {
+    let mut eeprom = M24C64::new(Async(sim::M24C64::new(0b011)), 0b011);
+    let data: [u8; 100] = core::array::from_fn(|i| i as u8);
+    block_on(eeprom.write(0x10, &data, &mut Async(NoDelay))).unwrap();
+
+    let mut buf = [0u8; 100];
+    block_on(eeprom.read(0x10, &mut buf)).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(block_on(eeprom.read_byte(0x20)), Ok(0x10));
+
+    let device = eeprom.release().0;
+    assert_eq!(&device.memory()[0x10..0x74], &data);
+    assert_eq!(device.page_write_cycles()[..5], [1, 1, 1, 1, 0]);
+  }
+
+  #[test]
+  fn write_cycle_times_out() {
+    let device = Async(sim::M24C64::new(0).with_write_cycle_polls(10));
+    let mut eeprom = M24C64::new(device, 0).with_write_polling(WritePolling { interval_us: 1_000, timeout_us: 5_000 });
+    assert_eq!(block_on(eeprom.write(0, &[0x12], &mut Async(NoDelay))), Err(Error::WriteTimeout));
+    assert!(eeprom.i2c_mut().0.is_busy());
+
+    // The next write waits out the rest of the write cycle
+    eeprom.set_write_polling(WritePolling::default());
+    block_on(eeprom.write(1, &[0x34], &mut Async(NoDelay))).unwrap();
+    assert_eq!(&eeprom.release().0.memory()[..2], &[0x12, 0x34]);
+  }
+
+  #[test]
+  fn missing_device_is_not_a_timeout() {
+    let mut eeprom = M24C64::new(Async(sim::M24C64::new(0b001)), 0b000);
+    let nack = Err(Error::Bus(SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))));
+    assert_eq!(block_on(eeprom.write(0, &[0x12], &mut Async(NoDelay))), nack);
+    assert_eq!(eeprom.i2c_mut().0.transactions(), 1);
+  }
+
+  #[test]
+  fn write_if_changed_skips_unchanged_pages() {
+    let mut eeprom = M24C64::new(Async(sim::M24C64::new(0)), 0);
+    let mut data = [0u8; 100];
+    data.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+
+    assert_eq!(block_on(eeprom.write_if_changed(0x10, &data, &mut Async(NoDelay))), Ok(4));
+    assert_eq!(block_on(eeprom.write_if_changed(0x10, &data, &mut Async(NoDelay))), Ok(0));
+    data[50] ^= 0xFF;
+    assert_eq!(block_on(eeprom.write_if_changed(0x10, &data, &mut Async(NoDelay))), Ok(1));
+
+    let device = eeprom.release().0;
+    assert_eq!(&device.memory()[0x10..0x74], &data);
+    assert_eq!(device.page_write_cycles()[..5], [1, 1, 2, 1, 0]);
+  }
+
+  #[test]
+  fn id_page_lock_is_detected() {
+    let mut eeprom = M24C64::new(Async(sim::M24C64::new(0)), 0);
+    block_on(eeprom.write_id_page(0, b"SN-0001", &mut Async(NoDelay))).unwrap();
+    assert_eq!(block_on(eeprom.is_id_page_locked()), Ok(false));
+    assert!(!eeprom.i2c_mut().0.is_id_page_locked());
+
+    block_on(eeprom.lock_id_page(&mut Async(NoDelay))).unwrap();
+    assert_eq!(block_on(eeprom.is_id_page_locked()), Ok(true));
+    assert_eq!(block_on(eeprom.write_id_page(0, b"SN-0002", &mut Async(NoDelay))), Err(Error::IdPageLocked));
+
+    let device = eeprom.release().0;
+    assert!(device.is_id_page_locked());
+    assert_eq!(&device.id_page()[..7], b"SN-0001");
+  }
+}
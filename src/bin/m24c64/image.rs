@@ -0,0 +1,148 @@
+//! EEPROM image files, as raw binary or Intel HEX
+
+use std::{error::Error, fs, path::Path};
+
+use ihex::Record;
+
+/// Format of an image file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+  /// Raw binary, starting at the given offset
+  Bin,
+  /// Intel HEX, carrying its own addresses
+  Hex,
+}
+
+impl Format {
+  /// The format given, or else the one suggested by the extension of `path`
+  pub fn for_path(format: Option<Format>, path: &Path) -> Format {
+    format.unwrap_or_else(|| match path.extension().and_then(|e| e.to_str()) {
+      Some("hex" | "ihex" | "ihx") => Format::Hex,
+      _ => Format::Bin,
+    })
+  }
+}
+
+/// Contiguous runs of data, and the addresses they belong at
+#[derive(Debug, Default)]
+pub struct Image {
+  pub segments: Vec<(usize, Vec<u8>)>,
+}
+
+impl Image {
+  /// Load an image from `path`. Binary images are placed at `offset`, HEX images at their own addresses.
+  pub fn load(path: &Path, format: Format, offset: usize) -> Result<Image, Box<dyn Error>> {
+    match format {
+      Format::Bin => Ok(Image { segments: vec![(offset, fs::read(path)?)] }),
+      Format::Hex => Self::parse_hex(&fs::read_to_string(path)?),
+    }
+  }
+
+  fn parse_hex(text: &str) -> Result<Image, Box<dyn Error>> {
+    let mut image = Image::default();
+    let mut base = 0usize;
+    for record in ihex::Reader::new(text) {
+      match record? {
+        Record::Data { offset, value } => {
+          let address = base + offset as usize;
+          // Merge with the previous segment when it carries straight on
+          match image.segments.last_mut() {
+            Some((start, data)) if *start + data.len() == address => data.extend_from_slice(&value),
+            _ => image.segments.push((address, value)),
+          }
+        },
+        Record::ExtendedSegmentAddress(segment) => base = (segment as usize) << 4,
+        Record::ExtendedLinearAddress(upper) => base = (upper as usize) << 16,
+        Record::EndOfFile => break,
+        Record::StartSegmentAddress { .. } | Record::StartLinearAddress(_) => (),
+      }
+    }
+    Ok(image)
+  }
+
+  /// Save `data`, read from address 0 of the device, to `path`
+  pub fn save(path: &Path, format: Format, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    match format {
+      Format::Bin => fs::write(path, data)?,
+      Format::Hex => fs::write(path, Self::to_hex(data)?)?,
+    }
+    Ok(())
+  }
+
+  fn to_hex(data: &[u8]) -> Result<String, Box<dyn Error>> {
+    let mut records = vec![];
+    for (i, chunk) in data.chunks(16).enumerate() {
+      let address = i * 16;
+      if address % 0x1_0000 == 0 && address > 0 {
+        records.push(Record::ExtendedLinearAddress((address >> 16) as u16));
+      }
+      records.push(Record::Data { offset: address as u16, value: chunk.to_vec() });
+    }
+    records.push(Record::EndOfFile);
+    Ok(ihex::create_object_file_representation(&records)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(records: &[Record]) -> String {
+    ihex::create_object_file_representation(records).unwrap()
+  }
+
+  #[test]
+  fn format_follows_the_extension() {
+    assert_eq!(Format::for_path(None, Path::new("dump.hex")), Format::Hex);
+    assert_eq!(Format::for_path(None, Path::new("dump.ihx")), Format::Hex);
+    assert_eq!(Format::for_path(None, Path::new("dump.bin")), Format::Bin);
+    assert_eq!(Format::for_path(None, Path::new("dump")), Format::Bin);
+    assert_eq!(Format::for_path(Some(Format::Bin), Path::new("dump.hex")), Format::Bin);
+  }
+
+  #[test]
+  fn contiguous_records_are_merged() {
+    let image = Image::parse_hex(&hex(&[
+      Record::Data { offset: 0x0000, value: vec![0x01; 16] },
+      Record::Data { offset: 0x0010, value: vec![0x02; 4] },
+      Record::Data { offset: 0x0040, value: vec![0x03; 2] },
+      Record::Data { offset: 0x0042, value: vec![0x04] },
+      Record::EndOfFile,
+    ])).unwrap();
+
+    let mut first = vec![0x01; 16];
+    first.extend_from_slice(&[0x02; 4]);
+    assert_eq!(image.segments, [(0x0000, first), (0x0040, vec![0x03, 0x03, 0x04])]);
+  }
+
+  #[test]
+  fn extended_addresses_are_applied() {
+    let image = Image::parse_hex(&hex(&[
+      Record::ExtendedSegmentAddress(0x0100),
+      Record::Data { offset: 0x0010, value: vec![0x01] },
+      Record::ExtendedLinearAddress(0x0001),
+      Record::Data { offset: 0x0020, value: vec![0x02] },
+      Record::StartLinearAddress(0x1234),
+      Record::EndOfFile,
+    ])).unwrap();
+
+    assert_eq!(image.segments, [(0x1010, vec![0x01]), (0x1_0020, vec![0x02])]);
+  }
+
+  #[test]
+  fn invalid_hex_is_rejected() {
+    assert!(Image::parse_hex(":0100000001FF\n").is_err());
+    assert!(Image::parse_hex("not hex\n").is_err());
+  }
+
+  #[test]
+  fn hex_round_trip() {
+    // Long enough to need an extended linear address record
+    let data: Vec<u8> = (0..0x1_0020).map(|i| (i % 251) as u8).collect();
+    let text = Image::to_hex(&data).unwrap();
+    assert!(text.contains(":020000040001F9"));
+
+    let image = Image::parse_hex(&text).unwrap();
+    assert_eq!(image.segments, [(0, data)]);
+  }
+}
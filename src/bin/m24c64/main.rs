@@ -0,0 +1,346 @@
+//! Host-side tool to dump, flash and verify M24C64 EEPROMs over Linux i2c-dev
+
+mod image;
+
+use std::{error::Error, ops::Range, path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use embedded_hal::{delay::DelayNs, i2c::{self, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation}};
+use grapple_m24c64::{sim::{self, NoDelay}, M24C64};
+use linux_embedded_hal::{i2cdev::linux::LinuxI2CError, Delay, I2CError, I2cdev};
+
+use image::{Format, Image};
+
+const CAPACITY: usize = M24C64::<()>::CAPACITY;
+
+#[derive(Parser)]
+#[command(name = "m24c64", version, about = "Dump, flash and verify M24C64 EEPROMs over Linux i2c-dev")]
+struct Cli {
+  /// I2C bus device
+  #[arg(short, long, default_value = "/dev/i2c-1")]
+  bus: PathBuf,
+  /// Levels of the E2, E1 and E0 pins, as a number from 0 to 7
+  #[arg(short, long, default_value = "0", value_parser = parse_e_pins)]
+  e_pins: u8,
+  /// Image file format, guessed from the file extension (.hex, .ihex, .ihx) if not given
+  #[arg(short, long)]
+  format: Option<Format>,
+  /// Use a simulated device instead of real hardware
+  #[arg(long)]
+  sim: bool,
+  /// Binary image the simulated device is loaded from, and saved back to afterwards
+  #[arg(long, value_name = "IMAGE", requires = "sim")]
+  sim_image: Option<PathBuf>,
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Read the whole EEPROM into an image file
+  Dump {
+    output: PathBuf,
+  },
+  /// Write an image into the EEPROM
+  Write {
+    image: PathBuf,
+    /// Address to place a binary image at
+    #[arg(short, long, default_value = "0", value_parser = parse_number)]
+    offset: usize,
+  },
+  /// Check that the EEPROM holds an image
+  Verify {
+    image: PathBuf,
+    /// Address a binary image is placed at
+    #[arg(short, long, default_value = "0", value_parser = parse_number)]
+    offset: usize,
+  },
+  /// Fill the whole EEPROM with a byte
+  Fill {
+    #[arg(value_parser = parse_byte)]
+    byte: u8,
+  },
+  /// Print a range of the EEPROM (`start..end` or `start+len`, the whole EEPROM if not given)
+  Hexdump {
+    #[arg(value_parser = parse_range)]
+    range: Option<Range<usize>>,
+  },
+  /// List every byte that differs between the EEPROM and an image
+  Diff {
+    image: PathBuf,
+    /// Address a binary image is placed at
+    #[arg(short, long, default_value = "0", value_parser = parse_number)]
+    offset: usize,
+  },
+}
+
+/// `I2cdev` sends each operation of a transaction as its own message, with a repeated START in between, but the
+/// driver relies on adjacent writes going out as one (the memory address, followed by the data). Merge them first.
+///
+/// The driver also polls for the end of the write cycle with zero-length writes, which adapters flagged
+/// `I2C_AQ_NO_ZERO_LEN` reject with `EOPNOTSUPP`. Those are sent as a 1-byte read instead, which moves the device's
+/// address counter on by one (nothing here relies on it).
+struct LinuxBus(I2cdev);
+
+/// Error from a [`LinuxBus`]. `I2CError` only treats `ENXIO` as a NACK, but some adapters (e.g. i2c-bcm2835 and
+/// i2c-designware) return `EREMOTEIO` instead, which the driver has to see as a NACK to poll the write cycle.
+#[derive(Debug)]
+struct BusError(I2CError);
+
+impl i2c::Error for BusError {
+  fn kind(&self) -> ErrorKind {
+    let errno = match self.0.inner() {
+      LinuxI2CError::Errno(errno) => Some(*errno),
+      LinuxI2CError::Io(e) => e.raw_os_error(),
+    };
+    match errno {
+      Some(libc::EREMOTEIO) => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+      _ => self.0.kind(),
+    }
+  }
+}
+
+impl ErrorType for LinuxBus {
+  type Error = BusError;
+}
+
+impl I2c for LinuxBus {
+  fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+    if operations.iter().all(|op| matches!(op, Operation::Write(bytes) if bytes.is_empty())) {
+      return self.0.read(address, &mut [0u8]).map_err(BusError);
+    }
+
+    let mut writes: Vec<Vec<u8>> = vec![];
+    let mut previous_write = false;
+    for op in operations.iter() {
+      match op {
+        Operation::Write(bytes) if previous_write => writes.last_mut().unwrap().extend_from_slice(bytes),
+        Operation::Write(bytes) => writes.push(bytes.to_vec()),
+        Operation::Read(_) => (),
+      }
+      previous_write = matches!(op, Operation::Write(_));
+    }
+
+    let mut writes = writes.iter();
+    let mut merged = vec![];
+    let mut previous_write = false;
+    for op in operations.iter_mut() {
+      match op {
+        Operation::Write(_) if previous_write => (),
+        Operation::Write(_) => merged.push(Operation::Write(writes.next().unwrap())),
+        Operation::Read(buf) => merged.push(Operation::Read(buf)),
+      }
+      previous_write = merged.last().is_some_and(|op| matches!(op, Operation::Write(_)));
+    }
+
+    self.0.transaction(address, &mut merged).map_err(BusError)
+  }
+}
+
+fn parse_number(s: &str) -> Result<usize, String> {
+  let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+    Some(hex) => usize::from_str_radix(hex, 16),
+    None => match s.strip_prefix("0b") {
+      Some(bin) => usize::from_str_radix(bin, 2),
+      None => s.parse(),
+    }
+  };
+  result.map_err(|e| format!("invalid number '{}': {}", s, e))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+  let n = parse_number(s)?;
+  u8::try_from(n).map_err(|_| format!("{} doesn't fit in a byte", n))
+}
+
+fn parse_e_pins(s: &str) -> Result<u8, String> {
+  match parse_number(s)? {
+    n @ 0..=7 => Ok(n as u8),
+    n => Err(format!("E-pin address {} is more than 3 bits", n)),
+  }
+}
+
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+  let range = if let Some((start, end)) = s.split_once("..") {
+    parse_number(start)?..parse_number(end)?
+  } else if let Some((start, len)) = s.split_once('+') {
+    let start = parse_number(start)?;
+    let end = start.checked_add(parse_number(len)?).ok_or_else(|| format!("range '{}' overflows", s))?;
+    start..end
+  } else {
+    return Err(format!("invalid range '{}', expected start..end or start+len", s));
+  };
+
+  match range.start <= range.end && range.end <= CAPACITY {
+    true => Ok(range),
+    false => Err(format!("range {:#x}..{:#x} is outside of the EEPROM", range.start, range.end)),
+  }
+}
+
+/// Print `data`, read from `address`, as hex and ASCII
+fn hexdump(address: usize, data: &[u8]) {
+  for (i, line) in data.chunks(16).enumerate() {
+    let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+    let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+    println!("{:04x}  {:<47}  |{}|", address + i * 16, hex.join(" "), ascii);
+  }
+}
+
+/// Read back every segment of `image`, calling `mismatch` with the address, stored byte and expected byte of each
+/// one that differs. Returns the number of differences.
+fn compare<I2C>(eeprom: &mut M24C64<I2C>, image: &Image, mut mismatch: impl FnMut(usize, u8, u8)) -> Result<usize, Box<dyn Error>>
+where
+  I2C: I2c,
+  I2C::Error: 'static
+{
+  let mut differences = 0;
+  for (address, expected) in &image.segments {
+    let mut stored = vec![0u8; expected.len()];
+    eeprom.read(*address, &mut stored)?;
+    for (i, (s, e)) in stored.iter().zip(expected).enumerate().filter(|(_, (s, e))| s != e) {
+      mismatch(address + i, *s, *e);
+      differences += 1;
+    }
+  }
+  Ok(differences)
+}
+
+fn run<I2C>(cli: Cli, eeprom: &mut M24C64<I2C>, delay: &mut dyn DelayNs) -> Result<ExitCode, Box<dyn Error>>
+where
+  I2C: I2c,
+  I2C::Error: 'static
+{
+  match cli.command {
+    Command::Dump { output } => {
+      let mut data = vec![0u8; CAPACITY];
+      eeprom.read(0, &mut data)?;
+      Image::save(&output, Format::for_path(cli.format, &output), &data)?;
+    },
+    Command::Write { image, offset } => {
+      let image = Image::load(&image, Format::for_path(cli.format, &image), offset)?;
+      for (address, data) in &image.segments {
+        eeprom.write(*address, data, delay)?;
+      }
+    },
+    Command::Verify { image, offset } => {
+      let image = Image::load(&image, Format::for_path(cli.format, &image), offset)?;
+      let mut first = None;
+      let differences = compare(eeprom, &image, |address, _, _| { first.get_or_insert(address); })?;
+      if let Some(first) = first {
+        eprintln!("verify failed: {} bytes differ, starting at {:#06x}", differences, first);
+        return Ok(ExitCode::FAILURE);
+      }
+      println!("OK");
+    },
+    Command::Fill { byte } => {
+      eeprom.write(0, &[byte; CAPACITY], delay)?;
+    },
+    Command::Hexdump { range } => {
+      let range = range.unwrap_or(0..CAPACITY);
+      let mut data = vec![0u8; range.len()];
+      eeprom.read(range.start, &mut data)?;
+      hexdump(range.start, &data);
+    },
+    Command::Diff { image, offset } => {
+      let image = Image::load(&image, Format::for_path(cli.format, &image), offset)?;
+      let differences = compare(eeprom, &image, |address, stored, expected| {
+        println!("{:04x}: {:02x} != {:02x}", address, stored, expected);
+      })?;
+      if differences > 0 {
+        return Ok(ExitCode::FAILURE);
+      }
+    },
+  }
+  Ok(ExitCode::SUCCESS)
+}
+
+/// Run against a simulated device, loaded from and saved back to `image` (as a raw binary) if given
+fn run_sim(cli: Cli, image: Option<PathBuf>) -> Result<ExitCode, Box<dyn Error>> {
+  let mut device = sim::M24C64::new(cli.e_pins);
+  if let Some(image) = &image {
+    if image.exists() {
+      let data = std::fs::read(image)?;
+      if data.len() > CAPACITY {
+        return Err("simulator image is larger than the EEPROM".into());
+      }
+      device.memory_mut()[..data.len()].copy_from_slice(&data);
+    }
+  }
+
+  let e_pins = cli.e_pins;
+  let mut eeprom = M24C64::new(device, e_pins);
+  let code = run(cli, &mut eeprom, &mut NoDelay)?;
+  if let Some(image) = &image {
+    std::fs::write(image, eeprom.release().memory())?;
+  }
+  Ok(code)
+}
+
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+
+  let result = match cli.sim {
+    true => {
+      let image = cli.sim_image.clone();
+      run_sim(cli, image)
+    },
+    false => I2cdev::new(&cli.bus)
+      .map_err(|e| format!("{}: {}", cli.bus.display(), e).into())
+      .and_then(|i2c| {
+        // Not every adapter can transfer the whole array in one go
+        let mut eeprom = M24C64::new(LinuxBus(i2c), cli.e_pins).with_max_read_len(256);
+        run(cli, &mut eeprom, &mut Delay)
+      }),
+  };
+
+  result.unwrap_or_else(|e| {
+    eprintln!("error: {}", e);
+    ExitCode::FAILURE
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn numbers_in_each_base() {
+    assert_eq!(parse_number("42"), Ok(42));
+    assert_eq!(parse_number("0x2A"), Ok(42));
+    assert_eq!(parse_number("0X2a"), Ok(42));
+    assert_eq!(parse_number("0b101010"), Ok(42));
+    assert!(parse_number("0x").is_err());
+    assert!(parse_number("0b2").is_err());
+    assert!(parse_number("-1").is_err());
+    assert!(parse_number("0x1_0000_0000_0000_0000").is_err());
+  }
+
+  #[test]
+  fn bytes_and_e_pins_are_range_checked() {
+    assert_eq!(parse_byte("0xFF"), Ok(0xFF));
+    assert!(parse_byte("256").is_err());
+
+    assert_eq!(parse_e_pins("0b111"), Ok(7));
+    assert_eq!(parse_e_pins("0"), Ok(0));
+    assert!(parse_e_pins("8").is_err());
+  }
+
+  #[test]
+  fn ranges_in_either_form() {
+    assert_eq!(parse_range("0x100..0x200"), Ok(0x100..0x200));
+    assert_eq!(parse_range("0x100+0x100"), Ok(0x100..0x200));
+    assert_eq!(parse_range("0..8192"), Ok(0..CAPACITY));
+    assert_eq!(parse_range("8192+0"), Ok(CAPACITY..CAPACITY));
+    assert!(parse_range("0x100").is_err());
+    assert!(parse_range("0x100..").is_err());
+  }
+
+  #[test]
+  fn ranges_outside_of_the_eeprom_are_rejected() {
+    assert!(parse_range("0x200..0x100").is_err());
+    assert!(parse_range("0..8193").is_err());
+    assert!(parse_range("8192+1").is_err());
+    assert!(parse_range(&format!("1+{}", usize::MAX)).unwrap_err().contains("overflows"));
+    assert!(parse_range(&format!("{}..{}", usize::MAX, usize::MAX)).unwrap_err().contains("outside"));
+  }
+}
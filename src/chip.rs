@@ -0,0 +1,64 @@
+//! Memory geometry of the parts in the M24Cxx (and compatible 24Cxx) EEPROM family
+
+/// Memory geometry of a member of the M24Cxx EEPROM family
+pub trait Chip {
+  /// Size of the memory array, in bytes
+  const CAPACITY: usize;
+  /// Size of a single write page, in bytes
+  const PAGE_SIZE: usize;
+  /// Number of memory address bytes sent after the device select code (1 or 2)
+  const ADDRESS_BYTES: usize;
+  /// Number of high memory address bits carried in the low bits of the device select code, in place of the
+  /// E pins with the same position (e.g. `1010 E2 E1 A16` on the M24M01 has one block bit).
+  const BLOCK_BITS: u32;
+}
+
+/// Parts with an Identification Page (the `-D` variants, e.g. M24C64-D), a lockable extra page of memory at
+/// a separate device address.
+pub trait IdPage: Chip {}
+
+macro_rules! chip {
+  ($(#[$meta:meta])* $name:ident, $capacity:expr, $page_size:expr, $address_bytes:expr, $block_bits:expr) => {
+    $(#[$meta])*
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct $name;
+
+    impl Chip for $name {
+      const CAPACITY: usize = $capacity;
+      const PAGE_SIZE: usize = $page_size;
+      const ADDRESS_BYTES: usize = $address_bytes;
+      const BLOCK_BITS: u32 = $block_bits;
+    }
+  };
+}
+
+chip!(/// M24C01 - 1 Kbit, 16 byte pages
+  M24C01, 128, 16, 1, 0);
+chip!(/// M24C02 - 2 Kbit, 16 byte pages
+  M24C02, 256, 16, 1, 0);
+chip!(/// M24C04 - 4 Kbit, 16 byte pages, A8 in place of E0
+  M24C04, 512, 16, 1, 1);
+chip!(/// M24C08 - 8 Kbit, 16 byte pages, A9..A8 in place of E1..E0
+  M24C08, 1024, 16, 1, 2);
+chip!(/// M24C16 - 16 Kbit, 16 byte pages, A10..A8 in place of the E pins
+  M24C16, 2048, 16, 1, 3);
+chip!(/// M24C32 - 32 Kbit, 32 byte pages
+  M24C32, 4096, 32, 2, 0);
+chip!(/// M24C64 - 64 Kbit, 32 byte pages
+  M24C64, 8192, 32, 2, 0);
+chip!(/// M24128 - 128 Kbit, 64 byte pages
+  M24128, 16384, 64, 2, 0);
+chip!(/// M24256 - 256 Kbit, 64 byte pages
+  M24256, 32768, 64, 2, 0);
+chip!(/// M24512 - 512 Kbit, 128 byte pages
+  M24512, 65536, 128, 2, 0);
+chip!(/// M24M01 - 1 Mbit, 256 byte pages, A16 in place of E0
+  M24M01, 131072, 256, 2, 1);
+chip!(/// M24M02 - 2 Mbit, 256 byte pages, A17..A16 in place of E1..E0
+  M24M02, 262144, 256, 2, 2);
+
+impl IdPage for M24C32 {}
+impl IdPage for M24C64 {}
+impl IdPage for M24128 {}
+impl IdPage for M24256 {}
+impl IdPage for M24512 {}
@@ -0,0 +1,239 @@
+//! Wear-leveled persistent counters (boot count, odometer, runtime).
+//!
+//! Rather than rewriting a fixed address, each update is written to the next slot in a ring, so every slot only
+//! sees one in `slots` writes. Each slot holds the value, a generation number (incremented on every write, used
+//! to find the newest slot), and a checksum, so a slot torn by a power loss is ignored and the previous value is
+//! recovered on mount.
+//!
+//! | Offset | Size | Field                  |
+//! |--------|------|------------------------|
+//! | 0      | 8    | Value (LE)             |
+//! | 8      | 8    | Generation (LE)        |
+//! | 16     | 4    | Checksum (LE)          |
+//!
+//! The generation is 64 bits so it never wraps, even over the rated endurance of the largest rings. A slot that
+//! straddles a page boundary is written as two page writes, and a power loss between them fails its checksum like any
+//! other torn write.
+
+use core::marker::PhantomData;
+
+use embedded_storage::Storage;
+
+use crate::record::{Checksum, Crc32, RecordError};
+
+/// Size of a counter slot, in bytes
+pub const SLOT_SIZE: usize = 20;
+
+/// Write cycles each byte is rated for (M24C64 at 25°C)
+pub const DEFAULT_ENDURANCE: u32 = 4_000_000;
+
+/// A wear-leveled counter over a ring of slots in a [`Storage`], with the checksum algorithm `K`.
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{counter::Counter, storage::EepromStorage, M24C64};
+/// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::record::RecordError<grapple_m24c64::Error<I2C::Error>>> {
+///
+/// // 48 slots (960 bytes) at 0x1C00
+/// let mut boot_count = Counter::<_>::mount(EepromStorage::new(M24C64::new(i2c, 0), delay), 0x1C00, 48)?;
+/// let boots = boot_count.increment()?;
+/// let remaining = boot_count.remaining_endurance();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Counter<S, K = Crc32> {
+  storage: S,
+  /// Start address of the ring
+  start: u32,
+  /// Number of slots in the ring
+  slots: usize,
+  /// Slot holding the newest value
+  current: usize,
+  /// Newest value
+  value: u64,
+  /// Number of writes made to the ring
+  generation: u64,
+  /// Write cycles each slot is rated for
+  endurance: u32,
+  _checksum: PhantomData<K>
+}
+
+impl<S, K> Counter<S, K> {
+  /// Current value of the counter
+  pub fn value(&self) -> u64 {
+    self.value
+  }
+
+  /// Number of times the counter has been written
+  pub fn writes(&self) -> u64 {
+    self.generation
+  }
+
+  /// Set the number of write cycles each slot is rated for, used to estimate the remaining endurance.
+  /// Defaults to [`DEFAULT_ENDURANCE`].
+  pub fn with_endurance(mut self, endurance: u32) -> Self {
+    self.endurance = endurance;
+    self
+  }
+
+  /// Estimated number of writes left before the most worn slot reaches its rated endurance
+  pub fn remaining_endurance(&self) -> u64 {
+    (self.endurance as u64 * self.slots as u64).saturating_sub(self.generation)
+  }
+
+  /// Release the underlying storage
+  pub fn release(self) -> S {
+    self.storage
+  }
+
+  fn address(&self, slot: usize) -> u32 {
+    self.start + (slot * SLOT_SIZE) as u32
+  }
+}
+
+impl<S: Storage, K: Checksum> Counter<S, K> {
+  /// Mount a counter over a ring of `slots` slots starting at `start`, recovering the newest valid value.
+  /// A never-used ring mounts with a value of 0.
+  /// Returns [`RecordError::TooLarge`] if the ring doesn't fit in the storage.
+  ///
+  /// # Panics
+  /// Panics if `slots` is less than 2.
+  pub fn mount(storage: S, start: u32, slots: usize) -> Result<Self, RecordError<S::Error>> {
+    assert!(slots >= 2, "a counter needs at least 2 slots");
+    if start as usize + slots * SLOT_SIZE > storage.capacity() {
+      return Err(RecordError::TooLarge);
+    }
+
+    let mut counter = Self {
+      storage, start, slots,
+      current: slots - 1, value: 0, generation: 0,
+      endurance: DEFAULT_ENDURANCE, _checksum: PhantomData
+    };
+
+    let mut newest: Option<(u64, usize, u64)> = None;
+    for slot in 0..slots {
+      let mut buf = [0u8; SLOT_SIZE];
+      counter.storage.read(counter.address(slot), &mut buf).map_err(RecordError::Storage)?;
+      let checksum = u32::from_le_bytes([buf[16], buf[17], buf[18], buf[19]]);
+      if K::checksum(&[&buf[0..16]]) != checksum {
+        continue;
+      }
+
+      let (mut value, mut generation) = ([0u8; 8], [0u8; 8]);
+      value.copy_from_slice(&buf[0..8]);
+      generation.copy_from_slice(&buf[8..16]);
+      let generation = u64::from_le_bytes(generation);
+      if newest.is_none_or(|(newest, _, _)| generation > newest) {
+        newest = Some((generation, slot, u64::from_le_bytes(value)));
+      }
+    }
+
+    if let Some((generation, slot, value)) = newest {
+      counter.generation = generation;
+      counter.current = slot;
+      counter.value = value;
+    }
+    Ok(counter)
+  }
+
+  /// Set the counter to `value`, writing it to the next slot in the ring
+  pub fn set(&mut self, value: u64) -> Result<(), RecordError<S::Error>> {
+    let slot = (self.current + 1) % self.slots;
+    let generation = self.generation + 1;
+
+    let mut buf = [0u8; SLOT_SIZE];
+    buf[0..8].copy_from_slice(&value.to_le_bytes());
+    buf[8..16].copy_from_slice(&generation.to_le_bytes());
+    let checksum = K::checksum(&[&buf[0..16]]);
+    buf[16..20].copy_from_slice(&checksum.to_le_bytes());
+    self.storage.write(self.address(slot), &buf).map_err(RecordError::Storage)?;
+
+    self.current = slot;
+    self.value = value;
+    self.generation = generation;
+    Ok(())
+  }
+
+  /// Add `n` to the counter, returning the new value
+  pub fn add(&mut self, n: u64) -> Result<u64, RecordError<S::Error>> {
+    self.set(self.value.saturating_add(n))?;
+    Ok(self.value)
+  }
+
+  /// Add one to the counter, returning the new value
+  pub fn increment(&mut self) -> Result<u64, RecordError<S::Error>> {
+    self.add(1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{device::{NoDelay, Ram}, sim::{self, fixture::{lose_power, release, storage, SimStorage}}, storage::EepromStorage};
+
+  #[test]
+  fn mount_recovers_from_a_torn_slot() {
+    // Slot 1 (0x14..0x28) straddles the page boundary at 0x20, so tear either of its two page writes
+    for seed in 0..8 {
+      for writes in 0..2 {
+        let mount = |storage: SimStorage| Counter::<_>::mount(storage, 0, 8).unwrap();
+        let mut counter = mount(storage(sim::M24C64::new(0).with_seed(seed)));
+        counter.set(100).unwrap();
+
+        let device = release(counter.release());
+        let (mut counter, _) = lose_power(device, writes, mount, Counter::release, |counter| {
+          assert!(counter.set(200).is_err());
+        });
+        assert_eq!((counter.value(), counter.writes()), (100, 1));
+
+        // The torn slot is simply overwritten by the next update
+        counter.set(300).unwrap();
+        let counter = Counter::<_>::mount(counter.release(), 0, 8).unwrap();
+        assert_eq!((counter.value(), counter.writes()), (300, 2));
+      }
+    }
+  }
+
+  #[test]
+  fn mount_skips_a_flipped_slot() {
+    let mut counter = Counter::<_>::mount(storage(sim::M24C64::new(0)), 0x100, 4).unwrap();
+    counter.set(7).unwrap();
+    counter.set(8).unwrap();
+
+    // Corrupt the value in the newest slot
+    let mut device = release(counter.release());
+    device.flip_bit(0x100 + SLOT_SIZE, 0);
+    let counter = Counter::<_>::mount(storage(device), 0x100, 4).unwrap();
+    assert_eq!((counter.value(), counter.writes()), (7, 1));
+  }
+
+  #[test]
+  fn ring_wraps_around() {
+    let mut counter = Counter::<_>::mount(EepromStorage::new(Ram::<256>::new(), NoDelay), 0, 4).unwrap();
+    for i in 1..=10 {
+      assert_eq!(counter.increment(), Ok(i));
+    }
+
+    let counter = Counter::<_>::mount(counter.release(), 0, 4).unwrap();
+    assert_eq!((counter.value(), counter.writes(), counter.current), (10, 10, 1));
+  }
+
+  #[test]
+  fn generation_keeps_counting_past_u32() {
+    let mut counter = Counter::<_>::mount(EepromStorage::new(Ram::<256>::new(), NoDelay), 0, 4).unwrap();
+    counter.generation = u32::MAX as u64 - 2;
+    for _ in 0..5 {
+      counter.increment().unwrap();
+    }
+
+    let counter = Counter::<_>::mount(counter.release(), 0, 4).unwrap();
+    assert_eq!((counter.value(), counter.writes()), (5, u32::MAX as u64 + 3));
+  }
+
+  #[test]
+  fn remaining_endurance_exceeds_u32() {
+    let mut counter = Counter::<_>::mount(EepromStorage::new(Ram::<32768>::new(), NoDelay), 0, 1600).unwrap();
+    counter.increment().unwrap();
+    assert_eq!(counter.remaining_endurance(), 6_400_000_000 - 1);
+  }
+}
@@ -0,0 +1,261 @@
+//! A common interface to EEPROM-like devices, so code built on top of them can be tested against RAM and deployed
+//! on the real chip unchanged.
+
+use core::{convert::Infallible, fmt};
+
+use embedded_hal::{delay::DelayNs, digital::OutputPin, i2c::{I2c, Operation}};
+
+use crate::{Chip, Error, M24Cxx};
+
+/// An EEPROM-like device: a byte-addressable memory array that is programmed a page at a time, with a write cycle
+/// to wait out after each page.
+///
+/// Implemented by the I2C driver ([`M24Cxx`]), by [`Ram`] for tests, and by
+/// [`Nvmem`](crate::nvmem::Nvmem) (with the `std` feature) for files. [`EepromStorage`](crate::storage::EepromStorage)
+/// implements the `embedded-storage` traits over any of them, for the layers built on those traits.
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{device::{NoDelay, Ram}, EepromDevice, Error};
+///
+/// fn save_serial<E: EepromDevice>(eeprom: &mut E, serial: u32) -> Result<(), Error<E::BusError>> {
+///   eeprom.write(0x00, &serial.to_le_bytes(), &mut NoDelay)
+/// }
+///
+/// let mut ram = Ram::<8192>::new();
+/// save_serial(&mut ram, 1234).unwrap();
+/// assert_eq!(&ram.as_array()[..4], &1234u32.to_le_bytes());
+/// ```
+pub trait EepromDevice {
+  /// Error returned by the underlying bus or file
+  type BusError: fmt::Debug;
+
+  /// Size of the memory array, in bytes
+  const CAPACITY: usize;
+  /// Size of a single write page, in bytes
+  const PAGE_SIZE: usize;
+
+  /// Read an arbitrary number of bytes from the device, starting at `address`.
+  /// Returns [`Error::AddressOutOfRange`] if the range does not fit in the device.
+  fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<Self::BusError>>;
+
+  /// Start programming `data` into the device at `address`, without waiting for the write cycle to complete.
+  /// Returns [`Error::AddressOutOfRange`] if the range does not fit within a single page.
+  fn write_page(&mut self, address: usize, data: &[u8]) -> Result<(), Error<Self::BusError>>;
+
+  /// Wait for the write cycle of the last page write to complete
+  fn wait_write_cycle(&mut self, delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>>;
+
+  /// Write an arbitrary number of bytes into the device, starting at `address`, a page at a time.
+  /// Once this returns `Ok`, the final write cycle has completed.
+  /// Returns [`Error::AddressOutOfRange`] without writing anything if the range does not fit in the device.
+  fn write(&mut self, address: usize, data: &[u8], delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>> {
+    check_range(Self::CAPACITY, address, data.len())?;
+
+    for (i, page) in pages(Self::PAGE_SIZE, address, data) {
+      self.write_page(i, page)?;
+      self.wait_write_cycle(delay)?;
+    }
+    Ok(())
+  }
+}
+
+/// Make sure `len` bytes starting at `address` fit within a memory array of `capacity` bytes, since the device
+/// would otherwise silently wrap around to the start of the array.
+pub(crate) fn check_range<E>(capacity: usize, address: usize, len: usize) -> Result<(), Error<E>> {
+  match address.checked_add(len) {
+    Some(end) if end <= capacity => Ok(()),
+    _ => Err(Error::AddressOutOfRange)
+  }
+}
+
+/// Make sure `len` bytes starting at `address` fit within a single page of the memory array
+pub(crate) fn check_page<E>(capacity: usize, page_size: usize, address: usize, len: usize) -> Result<(), Error<E>> {
+  check_range(capacity, address, len)?;
+  match len <= page_size - address % page_size {
+    true => Ok(()),
+    false => Err(Error::AddressOutOfRange)
+  }
+}
+
+/// Split `data`, to be written at `address`, into the pieces that fall within each page of `page_size` bytes.
+/// Each piece is yielded along with its address.
+pub(crate) fn pages(page_size: usize, address: usize, data: &[u8]) -> Pages<'_> {
+  Pages { page_size, address, data }
+}
+
+/// Iterator over the pages of a write, see [`pages`]
+pub(crate) struct Pages<'a> {
+  page_size: usize,
+  address: usize,
+  data: &'a [u8],
+}
+
+impl<'a> Iterator for Pages<'a> {
+  type Item = (usize, &'a [u8]);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.data.is_empty() {
+      return None;
+    }
+    let len = (self.page_size - self.address % self.page_size).min(self.data.len());
+    let (page, rest) = self.data.split_at(len);
+    let address = self.address;
+    self.address += len;
+    self.data = rest;
+    Some((address, page))
+  }
+}
+
+impl<I2C: I2c, C: Chip, WC: OutputPin> EepromDevice for M24Cxx<I2C, C, WC> {
+  type BusError = I2C::Error;
+
+  const CAPACITY: usize = C::CAPACITY;
+  const PAGE_SIZE: usize = C::PAGE_SIZE;
+
+  fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<Self::BusError>> {
+    M24Cxx::read(self, address, data)
+  }
+
+  fn write_page(&mut self, address: usize, data: &[u8]) -> Result<(), Error<Self::BusError>> {
+    check_page(C::CAPACITY, C::PAGE_SIZE, address, data.len())?;
+    let (device, cmd, start) = self.encode_address(address);
+    self.with_write_enabled(|eeprom| {
+      eeprom.i2c.transaction(device, &mut [Operation::Write(&cmd[start..]), Operation::Write(data)]).map_err(Error::Bus)
+    })
+  }
+
+  fn wait_write_cycle(&mut self, delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>> {
+    M24Cxx::wait_write_cycle(self, delay)
+  }
+
+  // The driver's own write keeps the Write Control pin released across pages, and copes with a device that is
+  // still busy from an earlier write
+  fn write(&mut self, address: usize, data: &[u8], delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>> {
+    M24Cxx::write(self, address, data, delay)
+  }
+}
+
+/// A delay that returns immediately, for devices without a write cycle to wait out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+  fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// An EEPROM held in RAM, as an `N` byte array with `PAGE_SIZE` byte pages (32 by default, as on the M24C64).
+/// Writes complete immediately, so there is no write cycle to wait for.
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{device::{NoDelay, Ram}, record::{Crc32, Records}, storage::EepromStorage};
+///
+/// let mut records = Records::<_, Crc32>::new(EepromStorage::new(Ram::<8192>::new(), NoDelay));
+/// records.write(0x100, 1, b"calibration").unwrap();
+///
+/// let mut buf = [0u8; 32];
+/// let info = records.read(0x100, &mut buf).unwrap();
+/// assert_eq!(&buf[..info.len], b"calibration");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ram<const N: usize, const PAGE_SIZE: usize = 32> {
+  data: [u8; N],
+}
+
+impl<const N: usize, const PAGE_SIZE: usize> Ram<N, PAGE_SIZE> {
+  /// Create a new, blank (all `0xFF`) device
+  pub fn new() -> Self {
+    Self::from_array([0xFF; N])
+  }
+
+  /// Create a new device holding `data`
+  pub fn from_array(data: [u8; N]) -> Self {
+    Self { data }
+  }
+
+  /// Contents of the memory array
+  pub fn as_array(&self) -> &[u8; N] {
+    &self.data
+  }
+
+  /// Contents of the memory array, for setting up or inspecting a test directly
+  pub fn as_array_mut(&mut self) -> &mut [u8; N] {
+    &mut self.data
+  }
+
+  /// Release the memory array
+  pub fn into_array(self) -> [u8; N] {
+    self.data
+  }
+}
+
+impl<const N: usize, const PAGE_SIZE: usize> Default for Ram<N, PAGE_SIZE> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const N: usize, const PAGE_SIZE: usize> EepromDevice for Ram<N, PAGE_SIZE> {
+  type BusError = Infallible;
+
+  const CAPACITY: usize = N;
+  const PAGE_SIZE: usize = PAGE_SIZE;
+
+  fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<Self::BusError>> {
+    check_range(N, address, data.len())?;
+    data.copy_from_slice(&self.data[address..(address + data.len())]);
+    Ok(())
+  }
+
+  fn write_page(&mut self, address: usize, data: &[u8]) -> Result<(), Error<Self::BusError>> {
+    check_page(N, PAGE_SIZE, address, data.len())?;
+    self.data[address..(address + data.len())].copy_from_slice(data);
+    Ok(())
+  }
+
+  fn wait_write_cycle(&mut self, _delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+  use super::*;
+  use crate::storage::EepromStorage;
+
+  #[test]
+  fn pages_split_on_page_boundaries() {
+    let data = [0u8; 70];
+    let split: Vec<(usize, usize)> = pages(32, 0x1C, &data).map(|(address, page)| (address, page.len())).collect();
+    assert_eq!(split, [(0x1C, 4), (0x20, 32), (0x40, 32), (0x60, 2)]);
+    assert_eq!(pages(32, 0x40, &[]).count(), 0);
+  }
+
+  #[test]
+  fn ram_rejects_writes_across_a_page() {
+    let mut ram = Ram::<256, 16>::new();
+    assert_eq!(ram.write_page(0x0E, &[0; 2]), Ok(()));
+    assert_eq!(ram.write_page(0x0E, &[0; 3]), Err(Error::AddressOutOfRange));
+    assert_eq!(ram.read(0xFF, &mut [0; 2]), Err(Error::AddressOutOfRange));
+
+    ram.write(0x0E, &[0xAB; 20], &mut NoDelay).unwrap();
+    assert_eq!(&ram.as_array()[0x0E..0x22], &[0xAB; 20]);
+  }
+
+  #[test]
+  fn storage_erases_whole_pages() {
+    let mut storage = EepromStorage::new(Ram::<256, 16>::from_array([0u8; 256]), NoDelay);
+    assert_eq!(storage.erase(0x08, 0x20), Err(Error::NotAligned));
+    assert_eq!(storage.erase(0x10, 0x110), Err(Error::AddressOutOfRange));
+    storage.erase(0x10, 0x30).unwrap();
+
+    let mut buf = [0u8; 0x40];
+    ReadNorFlash::read(&mut storage, 0, &mut buf).unwrap();
+    assert_eq!(&buf[..0x10], &[0x00; 0x10]);
+    assert_eq!(&buf[0x10..0x30], &[0xFF; 0x20]);
+    assert_eq!(&buf[0x30..], &[0x00; 0x10]);
+  }
+}
@@ -0,0 +1,65 @@
+use core::fmt;
+
+use embedded_hal::{digital, i2c::{self, ErrorKind}};
+use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind};
+
+/// Errors returned by the M24C64 Driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+  /// The underlying I2C bus (or nvmem file) returned an error
+  Bus(E),
+  /// The device did not finish its internal write cycle in time (longer than t_w)
+  WriteTimeout,
+  /// The requested address range does not fit within the device's memory array
+  AddressOutOfRange,
+  /// The E-pin address given to the driver does not select a valid device
+  InvalidDeviceAddress,
+  /// An erase was requested that does not start and end on a page boundary
+  NotAligned,
+  /// The Identification Page has been locked, and can no longer be written
+  IdPageLocked,
+  /// The Write Control pin could not be driven
+  WriteControl(digital::ErrorKind),
+  /// A value could not be serialized into, or deserialized from, the EEPROM
+  Serialization,
+  /// Data read back after a write did not match what was written, starting at `address`
+  VerifyFailed { address: usize },
+}
+
+impl<E: i2c::Error> i2c::Error for Error<E> {
+  fn kind(&self) -> ErrorKind {
+    match self {
+      Error::Bus(e) => e.kind(),
+      // Including WriteTimeout, which must not look like a NACK from a missing device
+      _ => ErrorKind::Other,
+    }
+  }
+}
+
+impl<E: fmt::Debug> NorFlashError for Error<E> {
+  fn kind(&self) -> NorFlashErrorKind {
+    match self {
+      Error::AddressOutOfRange => NorFlashErrorKind::OutOfBounds,
+      Error::NotAligned => NorFlashErrorKind::NotAligned,
+      _ => NorFlashErrorKind::Other,
+    }
+  }
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Bus(e) => write!(f, "bus error: {:?}", e),
+      Error::WriteTimeout => write!(f, "timed out waiting for the write cycle to complete"),
+      Error::AddressOutOfRange => write!(f, "address range is outside of the memory array"),
+      Error::InvalidDeviceAddress => write!(f, "invalid E-pin device address"),
+      Error::NotAligned => write!(f, "erase range is not aligned to a page boundary"),
+      Error::IdPageLocked => write!(f, "identification page is locked"),
+      Error::WriteControl(e) => write!(f, "write control pin error: {:?}", e),
+      Error::Serialization => write!(f, "value could not be serialized or deserialized"),
+      Error::VerifyFailed { address } => write!(f, "verification failed at address {:#x}", address),
+    }
+  }
+}
+
+impl<E: fmt::Debug> core::error::Error for Error<E> {}
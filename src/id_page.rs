@@ -0,0 +1,151 @@
+use embedded_hal::{delay::DelayNs, digital::OutputPin, i2c::{Error as _, ErrorKind, I2c, NoAcknowledgeSource, Operation}};
+
+use crate::{device::check_range, Error, IdPage, M24Cxx};
+
+/// Memory address of the Identification Page. The byte within the page is carried in the low address bits.
+pub(crate) const ID_PAGE_ADDRESS: [u8; 2] = [0x00, 0x00];
+/// Memory address used to lock the Identification Page (A10 set)
+pub(crate) const ID_LOCK_ADDRESS: [u8; 2] = [0x04, 0x00];
+/// Data byte that locks the Identification Page
+pub(crate) const ID_LOCK_DATA: u8 = 0x02;
+
+impl<I2C, C: IdPage, WC> M24Cxx<I2C, C, WC> {
+  /// The 7-bit I2C address of the Identification Page (`1011 E2 E1 E0`)
+  pub fn id_page_address(&self) -> u8 {
+    self.e_addr | 0x58
+  }
+
+  /// Make sure `len` bytes starting at `offset` fit within the Identification Page
+  pub(crate) fn check_id_range<E>(offset: usize, len: usize) -> Result<(), Error<E>> {
+    check_range(C::PAGE_SIZE, offset, len)
+  }
+
+  /// Address bytes for `offset` within the Identification Page
+  pub(crate) fn id_page_cmd(offset: usize) -> [u8; 2] {
+    [ID_PAGE_ADDRESS[0], ID_PAGE_ADDRESS[1] | offset as u8]
+  }
+}
+
+/// Whether the error from a lock status probe means the data byte was refused, i.e. the page is locked
+pub(crate) fn is_lock_nack(kind: ErrorKind) -> bool {
+  matches!(kind, ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data | NoAcknowledgeSource::Unknown))
+}
+
+impl<I2C, C, WC> M24Cxx<I2C, C, WC>
+where
+  I2C: I2c,
+  C: IdPage,
+  WC: OutputPin
+{
+  /// Read bytes from the Identification Page, starting at `offset` within the page.
+  /// Returns [`Error::AddressOutOfRange`] if the range does not fit within a single page.
+  pub fn read_id_page(&mut self, offset: usize, data: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    Self::check_id_range(offset, data.len())?;
+    self.i2c.write_read(self.id_page_address(), &Self::id_page_cmd(offset), data).map_err(Error::Bus)
+  }
+
+  /// Write bytes into the Identification Page, starting at `offset` within the page.
+  /// Returns [`Error::AddressOutOfRange`] if the range does not fit within a single page, or
+  /// [`Error::IdPageLocked`] if the page has been locked.
+  pub fn write_id_page(&mut self, offset: usize, data: &[u8], delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    Self::check_id_range(offset, data.len())?;
+    if self.is_id_page_locked()? {
+      return Err(Error::IdPageLocked);
+    }
+    let device = self.id_page_address();
+    self.with_write_enabled(|eeprom| eeprom.write_cmd(device, &Self::id_page_cmd(offset), data, delay))
+  }
+
+  /// Permanently lock the Identification Page, making it read-only. This cannot be undone.
+  pub fn lock_id_page(&mut self, delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    let device = self.id_page_address();
+    self.with_write_enabled(|eeprom| eeprom.write_cmd(device, &ID_LOCK_ADDRESS, &[ID_LOCK_DATA], delay))
+  }
+
+  /// Check whether the Identification Page has been locked.
+  pub fn is_id_page_locked(&mut self) -> Result<bool, Error<I2C::Error>> {
+    // Make sure the device is present and idle first, since a NACK of the data byte below is how a locked page
+    // is reported. The byte read back is also what the probe sends.
+    let mut current = [0u8];
+    self.read_id_page(0, &mut current)?;
+
+    // The probe is a truncated Identification Page write: the device ACKs the data byte if the page is unlocked,
+    // and the repeated start of the read aborts the write. It never addresses the lock register, so a HAL that ends
+    // the write with a STOP instead only rewrites the first byte of the page with its current value.
+    // The data byte is also refused while WC is high, so the pin has to be released for the probe.
+    let device = self.id_page_address();
+    self.with_write_enabled(|eeprom| {
+      let mut buf = [0u8];
+      let result = eeprom.i2c.transaction(device, &mut [
+        Operation::Write(&ID_PAGE_ADDRESS), Operation::Write(&current), Operation::Read(&mut buf)
+      ]);
+
+      match result {
+        Ok(_) => Ok(false),
+        Err(e) if is_lock_nack(e.kind()) => Ok(true),
+        Err(e) => Err(Error::Bus(e))
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use embedded_hal::i2c::{ErrorType, I2c, Operation};
+
+  use crate::{sim::{self, NoDelay}, M24C64};
+
+  /// A bus that ends the transfer with a STOP before every read, rather than a repeated START
+  struct StopBeforeRead(sim::M24C64);
+
+  impl ErrorType for StopBeforeRead {
+    type Error = sim::SimError;
+  }
+
+  impl I2c for StopBeforeRead {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+      let split = operations.iter().position(|op| matches!(op, Operation::Read(_))).unwrap_or(operations.len());
+      let (writes, reads) = operations.split_at_mut(split);
+      if !writes.is_empty() {
+        self.0.transaction(address, writes)?;
+      }
+      if !reads.is_empty() {
+        self.0.transaction(address, reads)?;
+      }
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn probe_leaves_unlocked_page_unlocked() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    eeprom.write_id_page(0, &[0x12, 0x34], &mut NoDelay).unwrap();
+    assert!(!eeprom.is_id_page_locked().unwrap());
+    assert!(!eeprom.is_id_page_locked().unwrap());
+
+    let device = eeprom.release();
+    assert!(!device.is_id_page_locked());
+    assert_eq!(&device.id_page()[..2], &[0x12, 0x34]);
+  }
+
+  #[test]
+  fn probe_without_repeated_start_cannot_lock() {
+    let device = sim::M24C64::new(0).with_write_cycle_polls(0);
+    let mut eeprom = M24C64::new(StopBeforeRead(device), 0);
+    eeprom.write_id_page(0, &[0xA5], &mut NoDelay).unwrap();
+    assert!(!eeprom.is_id_page_locked().unwrap());
+
+    let device = eeprom.release().0;
+    assert!(!device.is_id_page_locked());
+    assert_eq!(device.id_page()[0], 0xA5);
+  }
+
+  #[test]
+  fn probe_detects_locked_page() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    eeprom.lock_id_page(&mut NoDelay).unwrap();
+    assert!(eeprom.is_id_page_locked().unwrap());
+    assert_eq!(eeprom.write_id_page(0, &[0x00], &mut NoDelay), Err(crate::Error::IdPageLocked));
+    assert!(eeprom.release().is_id_page_locked());
+  }
+}
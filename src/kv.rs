@@ -0,0 +1,696 @@
+//! Wear-leveled, log-structured key-value store.
+//!
+//! The store's region is split into `N` sectors, which are written as a circular log. Updates are appended to
+//! the current sector rather than rewriting a fixed address, so writes (and wear) are spread across the whole
+//! region. When the current sector fills up the log moves on to the next one, and the sector after that (the
+//! oldest) is compacted: its live entries are copied forward, and it is released to be reused.
+//!
+//! Every sector header and entry carries a checksum, and entries are written before their header, so a write torn
+//! by a power loss is ignored on the next mount. An interrupted compaction is resumed when the store is mounted.
+//!
+//! Sector layout:
+//!
+//! | Offset | Size | Field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 4    | Sequence number (LE)                    |
+//! | 4      | 4    | Checksum over the sequence number (LE)  |
+//! | 8      | ...  | Entries                                 |
+//!
+//! Entry layout:
+//!
+//! | Offset | Size | Field                                                         |
+//! |--------|------|---------------------------------------------------------------|
+//! | 0      | 1    | Kind (`1` = set, `2` = remove)                                |
+//! | 1      | 1    | Key length                                                    |
+//! | 2      | 2    | Value length (LE)                                             |
+//! | 4      | 4    | Checksum over the sector sequence number, bytes 0..4, key and value (LE) |
+//! | 8      | ...  | Key, then value                                               |
+
+use core::{fmt, marker::PhantomData};
+
+use embedded_storage::Storage;
+
+use crate::record::{Checksum, Crc32};
+
+/// Longest key the store can hold, in bytes
+pub const MAX_KEY_LEN: usize = 32;
+
+const SECTOR_HEADER_SIZE: usize = 8;
+const ENTRY_HEADER_SIZE: usize = 8;
+const SECTOR_MAGIC: &[u8] = b"KVS0";
+const KIND_SET: u8 = 1;
+const KIND_REMOVE: u8 = 2;
+
+/// A type that can be used as a key in a [`KvStore`]
+pub trait Key {
+  /// Encode the key into `buf`, returning the number of bytes used, or `None` if the key is longer than
+  /// [`MAX_KEY_LEN`].
+  fn encode(&self, buf: &mut [u8; MAX_KEY_LEN]) -> Option<usize>;
+}
+
+macro_rules! int_key {
+  ($($ty:ty),*) => {
+    $(
+      impl Key for $ty {
+        fn encode(&self, buf: &mut [u8; MAX_KEY_LEN]) -> Option<usize> {
+          let bytes = self.to_le_bytes();
+          buf[..bytes.len()].copy_from_slice(&bytes);
+          Some(bytes.len())
+        }
+      }
+    )*
+  };
+}
+
+int_key!(u8, u16, u32, u64);
+
+impl Key for [u8] {
+  fn encode(&self, buf: &mut [u8; MAX_KEY_LEN]) -> Option<usize> {
+    buf.get_mut(..self.len())?.copy_from_slice(self);
+    Some(self.len())
+  }
+}
+
+impl Key for str {
+  fn encode(&self, buf: &mut [u8; MAX_KEY_LEN]) -> Option<usize> {
+    self.as_bytes().encode(buf)
+  }
+}
+
+impl<K: Key + ?Sized> Key for &K {
+  fn encode(&self, buf: &mut [u8; MAX_KEY_LEN]) -> Option<usize> {
+    (**self).encode(buf)
+  }
+}
+
+/// Errors returned by the key-value store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvError<E> {
+  /// The underlying storage returned an error
+  Storage(E),
+  /// There is no room left for the entry, even after compaction
+  Full,
+  /// The entry is larger than a sector can hold
+  TooLarge,
+  /// The key is longer than [`MAX_KEY_LEN`]
+  KeyTooLong,
+  /// The buffer given is too small to hold the value
+  BufferTooSmall {
+    /// Length of the stored value
+    required: usize
+  },
+}
+
+impl<E: fmt::Debug> fmt::Display for KvError<E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      KvError::Storage(e) => write!(f, "storage error: {:?}", e),
+      KvError::Full => write!(f, "key-value store is full"),
+      KvError::TooLarge => write!(f, "entry is too large for a sector"),
+      KvError::KeyTooLong => write!(f, "key is too long"),
+      KvError::BufferTooSmall { required } => write!(f, "buffer too small, value needs {} bytes", required),
+    }
+  }
+}
+
+impl<E: fmt::Debug> core::error::Error for KvError<E> {}
+
+/// Location and header of an entry
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+  sector: usize,
+  offset: usize,
+  kind: u8,
+  key_len: usize,
+  value_len: usize,
+}
+
+impl Entry {
+  fn size(&self) -> usize {
+    ENTRY_HEADER_SIZE + self.key_len + self.value_len
+  }
+}
+
+/// A wear-leveled key-value store over `N` sectors of a [`Storage`], with the checksum algorithm `K`.
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{kv::KvStore, storage::EepromStorage, M24C64};
+/// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::kv::KvError<grapple_m24c64::Error<I2C::Error>>> {
+///
+/// // The whole 8 KiB, as 32 sectors of 256 bytes
+/// let mut kv = KvStore::<_, 32>::mount(EepromStorage::new(M24C64::new(i2c, 0), delay), 0, 256)?;
+///
+/// kv.set("boot_count", &42u32.to_le_bytes())?;
+/// kv.set(7u16, b"seven")?;
+///
+/// let mut buf = [0u8; 4];
+/// if let Some(len) = kv.get("boot_count", &mut buf)? {
+///   assert_eq!(&buf[..len], &42u32.to_le_bytes());
+/// }
+/// kv.remove(7u16)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct KvStore<S, const N: usize, K = Crc32> {
+  storage: S,
+  /// Start address of the store's region
+  start: u32,
+  /// Size of each sector, in bytes
+  sector_size: usize,
+  /// Sequence number of each sector in use
+  sequences: [Option<u32>; N],
+  /// Offset of the end of the log within each sector
+  ends: [usize; N],
+  /// Sector currently being appended to
+  current: Option<usize>,
+  _checksum: PhantomData<K>
+}
+
+impl<S, const N: usize, K> KvStore<S, N, K> {
+  /// Release the underlying storage
+  pub fn release(self) -> S {
+    self.storage
+  }
+
+  /// Largest entry (key and value) a sector can hold
+  pub fn max_entry_len(&self) -> usize {
+    self.sector_size - SECTOR_HEADER_SIZE - ENTRY_HEADER_SIZE
+  }
+
+  fn address(&self, sector: usize, offset: usize) -> u32 {
+    self.start + (sector * self.sector_size + offset) as u32
+  }
+
+  fn next(sector: usize) -> usize {
+    (sector + 1) % N
+  }
+}
+
+impl<S: Storage, const N: usize, K: Checksum> KvStore<S, N, K> {
+  /// Mount a store of `N` sectors of `sector_size` bytes, starting at `start`, recovering from any interrupted
+  /// write or compaction. An empty (or never used) region mounts as an empty store.
+  ///
+  /// # Panics
+  /// Panics if `N` is less than 2, or `sector_size` can't hold a sector header and an entry.
+  pub fn mount(storage: S, start: u32, sector_size: usize) -> Result<Self, KvError<S::Error>> {
+    assert!(N >= 2, "a key-value store needs at least 2 sectors");
+    assert!(sector_size > SECTOR_HEADER_SIZE + ENTRY_HEADER_SIZE, "sectors are too small");
+
+    let mut kv = Self {
+      storage, start, sector_size,
+      sequences: [None; N], ends: [SECTOR_HEADER_SIZE; N], current: None,
+      _checksum: PhantomData
+    };
+
+    for sector in 0..N {
+      kv.sequences[sector] = kv.read_sector_header(sector)?;
+      if kv.sequences[sector].is_some() {
+        kv.ends[sector] = kv.find_end(sector)?;
+      }
+    }
+
+    kv.current = (0..N)
+      .filter_map(|sector| kv.sequences[sector].map(|seq| (seq, sector)))
+      .max()
+      .map(|(_, sector)| sector);
+
+    // The sector after the current one is always released after moving on to a new sector. If it isn't, the
+    // compaction was interrupted and has to be finished.
+    if let Some(current) = kv.current {
+      let victim = Self::next(current);
+      if victim != current && kv.sequences[victim].is_some() {
+        kv.compact(victim)?;
+      }
+    }
+
+    Ok(kv)
+  }
+
+  /// Erase the store, releasing every sector.
+  /// The log then carries on from the next sequence number, since entries are only released rather than erased and
+  /// would reappear under one they were written with. A format interrupted by a power loss may leave some entries.
+  pub fn format(&mut self) -> Result<(), KvError<S::Error>> {
+    let sequence = self.current.and_then(|current| self.sequences[current]).map_or(0, |seq| seq.wrapping_add(1));
+    for sector in 0..N {
+      self.release_sector(sector)?;
+    }
+    self.write_sector_header(0, sequence)?;
+    self.current = Some(0);
+    Ok(())
+  }
+
+  /// Read the value of `key` into `buf`, returning its length, or `None` if the key isn't present
+  pub fn get(&mut self, key: impl Key, buf: &mut [u8]) -> Result<Option<usize>, KvError<S::Error>> {
+    let mut key_buf = [0u8; MAX_KEY_LEN];
+    let key_len = key.encode(&mut key_buf).ok_or(KvError::KeyTooLong)?;
+
+    match self.find(&key_buf[..key_len])? {
+      Some(entry) if entry.kind == KIND_SET => {
+        if entry.value_len > buf.len() {
+          return Err(KvError::BufferTooSmall { required: entry.value_len });
+        }
+        let address = self.address(entry.sector, entry.offset + ENTRY_HEADER_SIZE + entry.key_len);
+        self.storage.read(address, &mut buf[..entry.value_len]).map_err(KvError::Storage)?;
+        Ok(Some(entry.value_len))
+      },
+      _ => Ok(None)
+    }
+  }
+
+  /// Whether `key` is present in the store
+  pub fn contains(&mut self, key: impl Key) -> Result<bool, KvError<S::Error>> {
+    let mut key_buf = [0u8; MAX_KEY_LEN];
+    let key_len = key.encode(&mut key_buf).ok_or(KvError::KeyTooLong)?;
+    Ok(matches!(self.find(&key_buf[..key_len])?, Some(entry) if entry.kind == KIND_SET))
+  }
+
+  /// Set `key` to `value`.
+  /// Returns [`KvError::Full`], without writing anything, if there is no room for the entry even after compaction.
+  /// The old value is only dropped once the new one has been written, so updating a key needs room as well.
+  pub fn set(&mut self, key: impl Key, value: &[u8]) -> Result<(), KvError<S::Error>> {
+    let mut key_buf = [0u8; MAX_KEY_LEN];
+    let key_len = key.encode(&mut key_buf).ok_or(KvError::KeyTooLong)?;
+    self.append(KIND_SET, &key_buf[..key_len], value)
+  }
+
+  /// Remove `key` from the store, if it is present
+  pub fn remove(&mut self, key: impl Key) -> Result<(), KvError<S::Error>> {
+    let mut key_buf = [0u8; MAX_KEY_LEN];
+    let key_len = key.encode(&mut key_buf).ok_or(KvError::KeyTooLong)?;
+    match self.find(&key_buf[..key_len])? {
+      Some(entry) if entry.kind == KIND_SET => self.append(KIND_REMOVE, &key_buf[..key_len], &[]),
+      _ => Ok(())
+    }
+  }
+
+  fn read_sector_header(&mut self, sector: usize) -> Result<Option<u32>, KvError<S::Error>> {
+    let mut header = [0u8; SECTOR_HEADER_SIZE];
+    self.storage.read(self.address(sector, 0), &mut header).map_err(KvError::Storage)?;
+    let sequence = [header[0], header[1], header[2], header[3]];
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok((K::checksum(&[SECTOR_MAGIC, &sequence]) == checksum).then_some(u32::from_le_bytes(sequence)))
+  }
+
+  fn write_sector_header(&mut self, sector: usize, sequence: u32) -> Result<(), KvError<S::Error>> {
+    let sequence_bytes = sequence.to_le_bytes();
+    let mut header = [0u8; SECTOR_HEADER_SIZE];
+    header[0..4].copy_from_slice(&sequence_bytes);
+    header[4..8].copy_from_slice(&K::checksum(&[SECTOR_MAGIC, &sequence_bytes]).to_le_bytes());
+    self.storage.write(self.address(sector, 0), &header).map_err(KvError::Storage)?;
+    self.sequences[sector] = Some(sequence);
+    self.ends[sector] = SECTOR_HEADER_SIZE;
+    Ok(())
+  }
+
+  fn release_sector(&mut self, sector: usize) -> Result<(), KvError<S::Error>> {
+    self.storage.write(self.address(sector, 0), &[0u8; SECTOR_HEADER_SIZE]).map_err(KvError::Storage)?;
+    self.sequences[sector] = None;
+    self.ends[sector] = SECTOR_HEADER_SIZE;
+    Ok(())
+  }
+
+  /// Read the header of the entry at `offset` in `sector`, without verifying it
+  fn read_entry(&mut self, sector: usize, offset: usize) -> Result<Option<(Entry, u32)>, KvError<S::Error>> {
+    if offset + ENTRY_HEADER_SIZE > self.sector_size {
+      return Ok(None);
+    }
+    let mut header = [0u8; ENTRY_HEADER_SIZE];
+    self.storage.read(self.address(sector, offset), &mut header).map_err(KvError::Storage)?;
+
+    let entry = Entry {
+      sector, offset,
+      kind: header[0],
+      key_len: header[1] as usize,
+      value_len: u16::from_le_bytes([header[2], header[3]]) as usize
+    };
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    let valid = (entry.kind == KIND_SET || entry.kind == KIND_REMOVE)
+      && entry.key_len <= MAX_KEY_LEN
+      && offset + entry.size() <= self.sector_size;
+    Ok(valid.then_some((entry, checksum)))
+  }
+
+  /// Checksum of an entry's contents, streamed from `sector` in small chunks
+  fn checksum_entry(&mut self, entry: &Entry, sequence: u32) -> Result<u32, KvError<S::Error>> {
+    let mut state = K::begin();
+    K::update(&mut state, &sequence.to_le_bytes());
+    K::update(&mut state, &[entry.kind, entry.key_len as u8]);
+    K::update(&mut state, &(entry.value_len as u16).to_le_bytes());
+
+    let mut chunk = [0u8; 32];
+    let body = entry.key_len + entry.value_len;
+    let mut i = 0;
+    while i < body {
+      let n = (body - i).min(chunk.len());
+      let address = self.address(entry.sector, entry.offset + ENTRY_HEADER_SIZE + i);
+      self.storage.read(address, &mut chunk[..n]).map_err(KvError::Storage)?;
+      K::update(&mut state, &chunk[..n]);
+      i += n;
+    }
+    Ok(K::finish(state))
+  }
+
+  /// Find the end of the log in `sector`: the first entry that is missing, or torn
+  fn find_end(&mut self, sector: usize) -> Result<usize, KvError<S::Error>> {
+    let sequence = self.sequences[sector].unwrap_or(0);
+    let mut offset = SECTOR_HEADER_SIZE;
+    while let Some((entry, checksum)) = self.read_entry(sector, offset)? {
+      if self.checksum_entry(&entry, sequence)? != checksum {
+        break;
+      }
+      offset += entry.size();
+    }
+    Ok(offset)
+  }
+
+  /// Find the newest entry for `key`, walking the sectors from oldest to newest
+  fn find(&mut self, key: &[u8]) -> Result<Option<Entry>, KvError<S::Error>> {
+    let Some(current) = self.current else { return Ok(None) };
+
+    let mut found = None;
+    let mut sector = Self::next(current);
+    for _ in 0..N {
+      if self.sequences[sector].is_some() {
+        let mut offset = SECTOR_HEADER_SIZE;
+        while offset < self.ends[sector] {
+          let Some((entry, _)) = self.read_entry(sector, offset)? else { break };
+          if entry.key_len == key.len() {
+            let mut entry_key = [0u8; MAX_KEY_LEN];
+            let address = self.address(sector, offset + ENTRY_HEADER_SIZE);
+            self.storage.read(address, &mut entry_key[..entry.key_len]).map_err(KvError::Storage)?;
+            if &entry_key[..entry.key_len] == key {
+              found = Some(entry);
+            }
+          }
+          offset += entry.size();
+        }
+      }
+      sector = Self::next(sector);
+    }
+    Ok(found)
+  }
+
+  /// Append an entry to the log, moving on to new sectors as needed
+  fn append(&mut self, kind: u8, key: &[u8], value: &[u8]) -> Result<(), KvError<S::Error>> {
+    let size = ENTRY_HEADER_SIZE + key.len() + value.len();
+    if key.len() + value.len() > self.max_entry_len() || value.len() > u16::MAX as usize {
+      return Err(KvError::TooLarge);
+    }
+
+    let current = match self.current {
+      Some(current) => current,
+      None => {
+        self.write_sector_header(0, 0)?;
+        self.current = Some(0);
+        0
+      }
+    };
+
+    // Check there will be room before moving on and compacting anything, so a full store isn't worn by every
+    // rejected write
+    if self.ends[current] + size > self.sector_size && !self.fits_after_compaction(size)? {
+      return Err(KvError::Full);
+    }
+
+    let mut current = current;
+    let mut moves = 0;
+    while self.ends[current] + size > self.sector_size {
+      // If every sector has been cycled through without making room, the live data fills the store
+      if moves == N {
+        return Err(KvError::Full);
+      }
+      current = self.next_sector()?;
+      moves += 1;
+    }
+
+    let sequence = self.sequences[current].unwrap_or(0);
+    let offset = self.ends[current];
+    let mut header = [kind, key.len() as u8, 0, 0, 0, 0, 0, 0];
+    header[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+    let checksum = K::checksum(&[&sequence.to_le_bytes(), &header[0..4], key, value]);
+    header[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+    // The body goes first, so an interrupted write never leaves a valid header in front of a torn body
+    self.storage.write(self.address(current, offset + ENTRY_HEADER_SIZE), key).map_err(KvError::Storage)?;
+    self.storage.write(self.address(current, offset + ENTRY_HEADER_SIZE + key.len()), value).map_err(KvError::Storage)?;
+    self.storage.write(self.address(current, offset), &header).map_err(KvError::Storage)?;
+    self.ends[current] = offset + size;
+    Ok(())
+  }
+
+  /// Whether an entry of `size` bytes fits once the log has moved on (up to once around) and compacted the sectors it
+  /// passes, following the same steps as [`KvStore::next_sector`] without writing anything
+  fn fits_after_compaction(&mut self, size: usize) -> Result<bool, KvError<S::Error>> {
+    let mut current = self.current.unwrap_or(0);
+    let mut ends = self.ends;
+    let mut in_use = self.sequences.map(|sequence| sequence.is_some());
+    // Live bytes of the sectors that compaction has copied entries into
+    let mut copied = [None; N];
+
+    for _ in 0..N {
+      current = Self::next(current);
+      ends[current] = SECTOR_HEADER_SIZE;
+      in_use[current] = true;
+      copied[current] = Some(0);
+
+      let victim = Self::next(current);
+      if in_use[victim] {
+        let live = match copied[victim] {
+          Some(live) => live,
+          None => self.live_bytes(victim)?,
+        };
+        if ends[current] + live > self.sector_size {
+          return Ok(false);
+        }
+        ends[current] += live;
+        copied[current] = Some(live);
+        in_use[victim] = false;
+      }
+
+      if ends[current] + size <= self.sector_size {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  /// Whether `entry` is the newest for its key, and so has to be kept when its sector is compacted
+  fn is_live(&mut self, entry: &Entry) -> Result<bool, KvError<S::Error>> {
+    if entry.kind != KIND_SET {
+      return Ok(false);
+    }
+    let mut key = [0u8; MAX_KEY_LEN];
+    self.storage.read(self.address(entry.sector, entry.offset + ENTRY_HEADER_SIZE), &mut key[..entry.key_len])
+      .map_err(KvError::Storage)?;
+    Ok(matches!(self.find(&key[..entry.key_len])?, Some(newest) if newest.sector == entry.sector && newest.offset == entry.offset))
+  }
+
+  /// Number of bytes compacting `sector` would copy forward
+  fn live_bytes(&mut self, sector: usize) -> Result<usize, KvError<S::Error>> {
+    let mut live = 0;
+    let mut offset = SECTOR_HEADER_SIZE;
+    while offset < self.ends[sector] {
+      let Some((entry, _)) = self.read_entry(sector, offset)? else { break };
+      offset += entry.size();
+      if self.is_live(&entry)? {
+        live += entry.size();
+      }
+    }
+    Ok(live)
+  }
+
+  /// Move the log on to the next sector, and compact the oldest sector after it
+  fn next_sector(&mut self) -> Result<usize, KvError<S::Error>> {
+    let current = self.current.unwrap_or(0);
+    let next = Self::next(current);
+    let sequence = self.sequences[current].map_or(0, |seq| seq.wrapping_add(1));
+
+    self.write_sector_header(next, sequence)?;
+    self.current = Some(next);
+
+    let victim = Self::next(next);
+    if self.sequences[victim].is_some() {
+      self.compact(victim)?;
+    }
+    Ok(next)
+  }
+
+  /// Copy the live entries of `victim` (the oldest sector) to the current sector, and release it
+  fn compact(&mut self, victim: usize) -> Result<(), KvError<S::Error>> {
+    let current = self.current.unwrap_or(0);
+    let sequence = self.sequences[current].unwrap_or(0);
+
+    let mut offset = SECTOR_HEADER_SIZE;
+    while offset < self.ends[victim] {
+      let Some((entry, _)) = self.read_entry(victim, offset)? else { break };
+      offset += entry.size();
+
+      // Only the newest entry for each key is kept. Removals can be dropped, since there is nothing older than the
+      // victim for them to hide.
+      if !self.is_live(&entry)? {
+        continue;
+      }
+
+      let dest = self.ends[current];
+      if dest + entry.size() > self.sector_size {
+        return Err(KvError::Full);
+      }
+
+      // Copy the body across in chunks, then write a header with the checksum for the new sector
+      let mut chunk = [0u8; 32];
+      let body = entry.key_len + entry.value_len;
+      let mut i = 0;
+      while i < body {
+        let n = (body - i).min(chunk.len());
+        self.storage.read(self.address(victim, entry.offset + ENTRY_HEADER_SIZE + i), &mut chunk[..n])
+          .map_err(KvError::Storage)?;
+        self.storage.write(self.address(current, dest + ENTRY_HEADER_SIZE + i), &chunk[..n])
+          .map_err(KvError::Storage)?;
+        i += n;
+      }
+
+      let moved = Entry { sector: current, offset: dest, ..entry };
+      let checksum = self.checksum_entry(&moved, sequence)?;
+      let mut header = [entry.kind, entry.key_len as u8, 0, 0, 0, 0, 0, 0];
+      header[2..4].copy_from_slice(&(entry.value_len as u16).to_le_bytes());
+      header[4..8].copy_from_slice(&checksum.to_le_bytes());
+      self.storage.write(self.address(current, dest), &header).map_err(KvError::Storage)?;
+      self.ends[current] = dest + entry.size();
+    }
+
+    self.release_sector(victim)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{device::{NoDelay, Ram}, sim::{self, fixture::{lose_power, release, storage, SimStorage}}, storage::EepromStorage};
+
+  /// Check that every key holds the expected value
+  fn check<S: Storage, const N: usize>(kv: &mut KvStore<S, N>, expected: &[Option<Vec<u8>>]) where S::Error: fmt::Debug {
+    for (key, value) in expected.iter().enumerate() {
+      let mut buf = [0u8; 64];
+      let len = kv.get(key as u8, &mut buf).unwrap();
+      assert_eq!(len.map(|len| &buf[..len]), value.as_deref(), "key {}", key);
+    }
+  }
+
+  #[test]
+  fn set_get_remove_across_sector_wraps() {
+    let storage = EepromStorage::new(Ram::<512>::new(), NoDelay);
+    let mut kv = KvStore::<_, 4>::mount(storage, 0, 128).unwrap();
+    let mut expected = vec![None; 5];
+
+    // Enough updates to go around the 4 sectors several times
+    for step in 0..200usize {
+      let key = step % 5;
+      if step % 7 == 3 {
+        kv.remove(key as u8).unwrap();
+        expected[key] = None;
+      } else {
+        let value = vec![step as u8; 1 + step % 13];
+        kv.set(key as u8, &value).unwrap();
+        expected[key] = Some(value);
+      }
+      check(&mut kv, &expected);
+
+      if step % 25 == 0 {
+        kv = KvStore::mount(kv.release(), 0, 128).unwrap();
+        check(&mut kv, &expected);
+      }
+    }
+    assert!(!kv.contains(99u8).unwrap());
+  }
+
+  #[test]
+  fn full_store_remounts() {
+    let storage = EepromStorage::new(Ram::<256>::new(), NoDelay);
+    let mut kv = KvStore::<_, 4>::mount(storage, 0, 64).unwrap();
+    let mut expected = Vec::new();
+    loop {
+      match kv.set(expected.len() as u8, &[expected.len() as u8; 8]) {
+        Ok(()) => expected.push(Some(vec![expected.len() as u8; 8])),
+        Err(KvError::Full) => break,
+        Err(e) => panic!("{:?}", e),
+      }
+    }
+    assert!(!expected.is_empty());
+    check(&mut kv, &expected);
+
+    let mut kv = KvStore::<_, 4>::mount(kv.release(), 0, 64).unwrap();
+    check(&mut kv, &expected);
+
+    // Every sector holds live data, so even an update has nowhere to go until the store is formatted
+    assert_eq!(kv.set(0u8, &[0xAA; 8]), Err(KvError::Full));
+    assert_eq!(kv.set(0u8, &[0xAA; 2]), Err(KvError::Full));
+    check(&mut kv, &expected);
+
+    kv.format().unwrap();
+    kv.set(0u8, &[0xAA; 8]).unwrap();
+    let mut kv = KvStore::<_, 4>::mount(kv.release(), 0, 64).unwrap();
+    check(&mut kv, &[Some(vec![0xAA; 8]), None]);
+  }
+
+  #[test]
+  fn rejected_sets_write_nothing() {
+    let mut kv = KvStore::<_, 4>::mount(storage(sim::M24C64::new(0)), 0, 64).unwrap();
+    let mut key = 0u8;
+    while kv.set(key, &[key; 8]).is_ok() {
+      key += 1;
+    }
+
+    let device = release(kv.release());
+    let cycles = device.page_write_cycles().iter().sum::<u32>();
+    let mut kv = KvStore::<_, 4>::mount(storage(device), 0, 64).unwrap();
+    for _ in 0..10 {
+      assert_eq!(kv.set(key, &[key; 8]), Err(KvError::Full));
+      assert_eq!(kv.set(0u8, &[0xAA; 8]), Err(KvError::Full));
+    }
+    assert_eq!(release(kv.release()).page_write_cycles().iter().sum::<u32>(), cycles);
+  }
+
+  #[test]
+  fn remounts_after_power_loss_at_every_write() {
+    // 4 entries of 29 bytes fill a sector, so the workload appends, moves on and compacts several times over
+    let workload = |step: usize| (step % 3, vec![step as u8; 20]);
+    let mount = |storage: SimStorage| KvStore::<_, 4>::mount(storage, 0, 128).unwrap();
+    let mut writes = 0;
+    loop {
+      let mut expected = vec![None; 3];
+      let device = sim::M24C64::new(0).with_seed(writes as u64);
+      let (mut kv, interrupted) = lose_power(device, writes, mount, KvStore::release, |kv| {
+        for step in 0..30 {
+          let (key, value) = workload(step);
+          match kv.set(key as u8, &value) {
+            Ok(()) => expected[key] = Some(value),
+            Err(_) => return Some((key, value)),
+          }
+        }
+        None
+      });
+      let Some((key, value)) = interrupted else {
+        break;
+      };
+
+      // The interrupted set either happened or it didn't
+      let mut buf = [0u8; 64];
+      let len = kv.get(key as u8, &mut buf).unwrap();
+      if len.map(|len| &buf[..len]) == Some(&value[..]) {
+        expected[key] = Some(value);
+      }
+      check(&mut kv, &expected);
+
+      // And the store carries on working
+      kv.set(key as u8, b"after").unwrap();
+      expected[key] = Some(b"after".to_vec());
+      let mut kv = KvStore::<_, 4>::mount(kv.release(), 0, 128).unwrap();
+      check(&mut kv, &expected);
+
+      writes += 1;
+    }
+    assert!(writes > 90, "only {} page writes", writes);
+  }
+}
@@ -1,103 +1,440 @@
 // Based off the m24c64 crate, with some changes to support writing arbitrary lengths of data
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 #![doc = include_str!("../README.md")]
 
-use embedded_hal::{delay::DelayNs, i2c::I2c};
+use core::marker::PhantomData;
 
-/// M24C64 Driver
-pub struct M24C64<I2C> {
+use embedded_hal::{delay::DelayNs, digital::OutputPin, i2c::{self, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation}};
+
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod chip;
+pub mod counter;
+pub mod device;
+pub mod kv;
+#[cfg(feature = "std")]
+pub mod nvmem;
+pub mod partition;
+pub mod record;
+pub mod sim;
+pub mod slots;
+pub mod storage;
+mod error;
+mod id_page;
+mod readback;
+#[cfg(any(feature = "bytemuck", feature = "serde"))]
+mod typed;
+mod write_control;
+
+pub use chip::{Chip, IdPage};
+pub use device::EepromDevice;
+pub use error::Error;
+pub use write_control::NoWriteControl;
+
+use device::{check_range, pages};
+
+/// Levels of the E2, E1 and E0 chip enable pins, which select the device's address on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EPins {
+  /// Level of the E2 pin
+  pub e2: bool,
+  /// Level of the E1 pin
+  pub e1: bool,
+  /// Level of the E0 pin
+  pub e0: bool,
+}
+
+impl EPins {
+  /// Create a new set of E pin levels
+  pub const fn new(e2: bool, e1: bool, e0: bool) -> Self {
+    Self { e2, e1, e0 }
+  }
+
+  /// Create a set of E pin levels from their bits (`0bE2E1E0`), returning `None` if `bits` is outside of 0..=7
+  pub const fn from_bits(bits: u8) -> Option<Self> {
+    if bits > 0b111 {
+      return None;
+    }
+    Some(Self::new(bits & 0b100 != 0, bits & 0b010 != 0, bits & 0b001 != 0))
+  }
+
+  /// The E pin levels as bits (`0bE2E1E0`)
+  pub const fn bits(&self) -> u8 {
+    ((self.e2 as u8) << 2) | ((self.e1 as u8) << 1) | (self.e0 as u8)
+  }
+}
+
+/// How the driver polls for the completion of the device's internal write cycle.
+///
+/// After a write, the EEPROM disconnects itself from the bus (NACKs its address) until the write has been
+/// performed internally. The driver sends address-only probes every `interval_us` until the device acknowledges,
+/// or reports [`Error::WriteTimeout`] once `timeout_us` has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WritePolling {
+  /// Time between probes, in microseconds
+  pub interval_us: u32,
+  /// Time after which the write is considered to have failed, in microseconds
+  pub timeout_us: u32,
+}
+
+impl Default for WritePolling {
+  /// Probe every 1ms, for up to 10ms (2*t_w in the M24C64 datasheet)
+  fn default() -> Self {
+    Self { interval_us: 1_000, timeout_us: 10_000 }
+  }
+}
+
+/// Whether an I2C error is the device not acknowledging its address, i.e. being busy with a write cycle.
+/// A NACK on a data byte (e.g. with WC held high) is a refusal, not a busy device.
+pub(crate) fn is_nack<E: i2c::Error>(e: &E) -> bool {
+  matches!(e.kind(), ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address | NoAcknowledgeSource::Unknown))
+}
+
+/// M24Cxx Driver, generic over the geometry of the part (see [`chip`])
+pub struct M24Cxx<I2C, C, WC = NoWriteControl> {
   /// I2C Interface
   i2c: I2C,
   /// Address set by the E pins
   e_addr: u8,
-  /// Command Buffer
-  cmd_buf: [u8; 34]
+  /// Write Control pin
+  wc: WC,
+  /// Whether the Write Control pin is currently released (low)
+  write_enabled: bool,
+  /// Write cycle completion polling
+  polling: WritePolling,
+  /// Whether a write cycle may still be in progress, because waiting for it timed out
+  write_pending: bool,
+  /// Largest number of bytes to read in a single I2C transfer
+  max_read_len: Option<usize>,
+  _chip: PhantomData<C>
 }
 
-impl<I2C> M24C64<I2C> {
-  /// Create a new instance of the M24C64 Driver
+aliases!(M24Cxx);
+
+impl<I2C, C: Chip> M24Cxx<I2C, C, NoWriteControl> {
+  /// Create a new instance of the driver
   /// # Arguments
   /// * `i2c` - I2C Interface (from the embedded-hal crate)
-  /// * `e_addr` - The address set on the E pins
+  /// * `e_addr` - The address set on the E pins, as `0bE2E1E0`
+  ///
+  /// # Panics
+  /// Panics if `e_addr` is outside of 0..=7, or sets an E pin that the part uses as a block select bit instead.
+  /// Use [`M24Cxx::try_new`] or [`M24Cxx::with_e_pins`] for a checked alternative.
   ///
   /// # Example
   /// ```
   /// use grapple_m24c64::M24C64;
+  /// # fn example(i2c: impl embedded_hal::i2c::I2c) {
   ///
   /// let eeprom = M24C64::new(i2c, 0);
+  /// # }
   /// ```
   pub fn new(i2c: I2C, e_addr: u8) -> Self {
-    Self {
-      i2c, e_addr,
-      cmd_buf: [0u8; 34]
+    match EPins::from_bits(e_addr).and_then(|e_pins| Self::build(i2c, e_pins)) {
+      Some(eeprom) => eeprom,
+      None => panic!("E-pin address is not valid for this device")
+    }
+  }
+
+  /// Create a new instance of the driver, returning [`Error::InvalidDeviceAddress`] if `e_addr` is outside of
+  /// 0..=7 (e.g. an already-shifted 7-bit address) or sets an E pin the part uses as a block select bit.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::{Error, M24C64};
+  /// # fn example(i2c: impl embedded_hal::i2c::I2c) {
+  ///
+  /// // 0x52 is the 7-bit bus address, not the E pins
+  /// assert!(matches!(M24C64::try_new(i2c, 0x52), Err(Error::InvalidDeviceAddress)));
+  /// # }
+  /// ```
+  pub fn try_new(i2c: I2C, e_addr: u8) -> Result<Self, Error<I2C::Error>> where I2C: ErrorType {
+    EPins::from_bits(e_addr)
+      .and_then(|e_pins| Self::build(i2c, e_pins))
+      .ok_or(Error::InvalidDeviceAddress)
+  }
+
+  /// Create a new instance of the driver from the levels of its E pins
+  ///
+  /// # Panics
+  /// Panics if an E pin that the part uses as a block select bit is set.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::{EPins, M24C64};
+  /// # fn example(i2c: impl embedded_hal::i2c::I2c) {
+  ///
+  /// let eeprom = M24C64::with_e_pins(i2c, EPins::new(false, true, false));
+  /// assert_eq!(eeprom.address(), 0x52);
+  /// # }
+  /// ```
+  pub fn with_e_pins(i2c: I2C, e_pins: EPins) -> Self {
+    match Self::build(i2c, e_pins) {
+      Some(eeprom) => eeprom,
+      None => panic!("E-pin address is not valid for this device")
+    }
+  }
+
+  /// Create a new instance of the driver from the levels of its E pins, returning
+  /// [`Error::InvalidDeviceAddress`] if an E pin that the part uses as a block select bit is set.
+  pub fn try_with_e_pins(i2c: I2C, e_pins: EPins) -> Result<Self, Error<I2C::Error>> where I2C: ErrorType {
+    Self::build(i2c, e_pins).ok_or(Error::InvalidDeviceAddress)
+  }
+
+  /// Create the driver, or `None` if `e_pins` sets an E pin that the part uses as a block select bit
+  fn build(i2c: I2C, e_pins: EPins) -> Option<Self> {
+    if e_pins.bits() & Self::block_mask() != 0 {
+      return None;
     }
+    Some(Self {
+      i2c, e_addr: e_pins.bits(), wc: NoWriteControl, write_enabled: false,
+      polling: WritePolling::default(), write_pending: false, max_read_len: None, _chip: PhantomData
+    })
+  }
+}
+
+impl<I2C, C: Chip, WC> M24Cxx<I2C, C, WC> {
+  /// Size of the memory array, in bytes
+  pub const CAPACITY: usize = C::CAPACITY;
+  /// Size of a single write page, in bytes
+  pub const PAGE_SIZE: usize = C::PAGE_SIZE;
+
+  /// Set how the completion of the internal write cycle is polled for
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::{M24C64, WritePolling};
+  /// # fn example(i2c: impl embedded_hal::i2c::I2c) {
+  ///
+  /// // Part with a 5ms t_w
+  /// let eeprom = M24C64::new(i2c, 0).with_write_polling(WritePolling { interval_us: 500, timeout_us: 5_000 });
+  /// # }
+  /// ```
+  pub fn with_write_polling(mut self, polling: WritePolling) -> Self {
+    self.polling = polling;
+    self
+  }
+
+  /// Set how the completion of the internal write cycle is polled for
+  pub fn set_write_polling(&mut self, polling: WritePolling) {
+    self.polling = polling;
+  }
+
+  /// How the completion of the internal write cycle is polled for
+  pub fn write_polling(&self) -> WritePolling {
+    self.polling
+  }
+
+  /// Limit the number of bytes read in a single I2C transfer, for I2C peripherals (or DMA channels) that can only
+  /// transfer so much at once. Reads are otherwise sent as one sequential read.
+  pub fn with_max_read_len(mut self, max_read_len: usize) -> Self {
+    self.set_max_read_len(Some(max_read_len));
+    self
+  }
+
+  /// Limit the number of bytes read in a single I2C transfer, or `None` for no limit
+  pub fn set_max_read_len(&mut self, max_read_len: Option<usize>) {
+    self.max_read_len = max_read_len.map(|len| len.max(1));
+  }
+
+  /// The effective 7-bit I2C address used to talk to the device.
+  /// For parts with block select bits, this is the address of the first block.
+  pub fn address(&self) -> u8 {
+    self.e_addr | 0x50
+  }
+
+  /// Mutable access to the I2C interface, e.g. to reconfigure the bus speed.
+  /// Don't start a transaction with the device from here while a write cycle is still in progress.
+  pub fn i2c_mut(&mut self) -> &mut I2C {
+    &mut self.i2c
+  }
+
+  /// Destroy the driver, giving back the I2C interface (and dropping the Write Control pin, if any).
+  ///
+  /// To share the bus with other devices without giving it up, use one of the `embedded-hal-bus` wrappers instead:
+  ///
+  /// ```
+  /// use core::cell::RefCell;
+  /// use embedded_hal_bus::i2c::RefCellDevice;
+  /// use grapple_m24c64::M24C64;
+  /// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, mut delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::Error<I2C::Error>> {
+  ///
+  /// let bus = RefCell::new(i2c);
+  /// let mut eeprom = M24C64::new(RefCellDevice::new(&bus), 0);
+  /// let mut other = M24C64::new(RefCellDevice::new(&bus), 1);
+  ///
+  /// eeprom.write(0x00, &[0x01, 0x02], &mut delay)?;
+  /// other.write(0x00, &[0x03, 0x04], &mut delay)?;
+  ///
+  /// drop((eeprom, other));
+  /// let i2c = bus.into_inner();
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn release(self) -> I2C {
+    self.i2c
+  }
+
+  /// Destroy the driver, giving back the I2C interface and the Write Control pin
+  pub fn release_parts(self) -> (I2C, WC) {
+    (self.i2c, self.wc)
+  }
+
+  /// Mask of the block select bits in the device select code
+  fn block_mask() -> u8 {
+    ((1u32 << C::BLOCK_BITS) - 1) as u8
+  }
+
+  /// Number of bytes addressable without changing the block select bits
+  fn block_size() -> usize {
+    1 << (8 * C::ADDRESS_BYTES)
+  }
+
+  /// Length of the next sequential read of up to `remaining` bytes from `address`. Reads are split where the
+  /// block select bits change, and to respect the maximum read length.
+  pub(crate) fn read_chunk_len(&self, address: usize, remaining: usize) -> usize {
+    let block_remaining = Self::block_size() - (address % Self::block_size());
+    remaining.min(block_remaining).min(self.max_read_len.unwrap_or(usize::MAX))
+  }
+
+  /// Split a memory address into the 7-bit I2C address of its block, and the memory address bytes to send
+  pub(crate) fn encode_address(&self, address: usize) -> (u8, [u8; 2], usize) {
+    let block = ((address >> (8 * C::ADDRESS_BYTES)) as u8) & Self::block_mask();
+    let bytes = (address as u16).to_be_bytes();
+    (self.address() | block, bytes, 2 - C::ADDRESS_BYTES)
   }
 }
 
-impl<I2C> M24C64<I2C>
+impl<I2C, C, WC> M24Cxx<I2C, C, WC>
 where
-  I2C: I2c
+  I2C: I2c,
+  C: Chip,
+  WC: OutputPin
 {
 
-  fn write_raw(&mut self, address: usize, bytes: &[u8], delay: &mut dyn DelayNs) -> Result<(), I2C::Error> {
-    self.cmd_buf[0] = (address >> 8) as u8;
-    self.cmd_buf[1] = (address & 0xFF) as u8;
-    self.cmd_buf[2..(bytes.len() + 2)].copy_from_slice(bytes);
+  pub(crate) fn write_raw(&mut self, address: usize, bytes: &[u8], delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    let (device, cmd, start) = self.encode_address(address);
+    self.write_cmd(device, &cmd[start..], bytes, delay)
+  }
 
-    // Wait until the device is connected to the bus
-    // After a write, the EEPROM disconnects itself from the bus until it can perform the write internally,
-    // thus we have to continually poll the i2c bus for the device to be ready to receive new bytes.
-    // while self.i2c.write(self.e_addr | 0x50, &[]).is_err() { }
+  /// Send a write of `cmd` (the memory address) followed by `bytes` to `device`, and wait for the device to
+  /// finish its internal write cycle.
+  fn write_cmd(&mut self, device: u8, cmd: &[u8], bytes: &[u8], delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    // Adjacent writes in a transaction are sent without a restart, so the address and data go out as one write
+    match self.i2c.transaction(device, &mut [Operation::Write(cmd), Operation::Write(bytes)]) {
+      Ok(_) => (),
+      // Still busy with a write cycle that previously timed out. Any other NACK means there is no device to talk to.
+      Err(e) if is_nack(&e) && self.write_pending => {
+        self.wait_write_cycle(delay)?;
+        self.i2c.transaction(device, &mut [Operation::Write(cmd), Operation::Write(bytes)]).map_err(Error::Bus)?;
+      },
+      Err(e) => return Err(Error::Bus(e))
+    }
 
-    // Slight modification - keep track of the retries, since if we're over t_w from the datasheet, we want
-    // to report an error instead of infinitely looping.
-    let mut i = 0;
+    self.wait_write_cycle(delay)
+  }
+
+  /// Wait for the device to finish its internal write cycle, by polling it with address-only writes until it
+  /// acknowledges (see [`WritePolling`]).
+  /// Only a NACK means the device is busy, anything else is a genuine bus fault and is reported straight away.
+  pub fn wait_write_cycle(&mut self, delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    let mut elapsed = 0;
     loop {
-      match self.i2c.write(self.e_addr | 0x50, &self.cmd_buf[0..bytes.len() + 2]) {
-        Ok(_) => return Ok(()),
-        Err(_) if i < 10 => (),
-        Err(e) => return Err(e)
+      match self.i2c.write(self.address(), &[]) {
+        Ok(_) => {
+          self.write_pending = false;
+          return Ok(());
+        },
+        Err(e) if !is_nack(&e) => return Err(Error::Bus(e)),
+        Err(_) if elapsed >= self.polling.timeout_us => {
+          self.write_pending = true;
+          return Err(Error::WriteTimeout);
+        },
+        Err(_) => ()
       }
-      i += 1;
-      delay.delay_ms(1)
+      delay.delay_us(self.polling.interval_us);
+      // A zero interval still has to count towards the timeout
+      elapsed = elapsed.saturating_add(self.polling.interval_us.max(1));
     }
   }
 
-  fn read_raw(&mut self, address: usize, bytes: &mut [u8]) -> Result<(), I2C::Error> {
-    self.cmd_buf[0] = (address >> 8) as u8;
-    self.cmd_buf[1] = (address & 0xFF) as u8;
-
-    self.i2c.write_read(self.e_addr | 0x50, &self.cmd_buf[0..2], bytes)
+  fn read_raw(&mut self, address: usize, bytes: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    let (device, cmd, start) = self.encode_address(address);
+    self.i2c.write_read(device, &cmd[start..], bytes).map_err(Error::Bus)
   }
 
   /// Write an arbitrary number of bytes into the EEPROM, starting at `address`.
-  /// This function will automatically paginate.
-  pub fn write(&mut self, address: usize, data: &[u8], delay: &mut dyn DelayNs) -> Result<(), I2C::Error> {
-    // Chunk the write into pages
-    let mut i = address;
-    while i < (address + data.len()) {
-      let page_offset = i % 32;
-      self.write_raw(i, &data[(i - address)..(i - address + (32 - page_offset)).min(data.len())], delay)?;
-      i += 32 - page_offset;
-    }
-    Ok(())
+  /// This function will automatically paginate, and releases the Write Control pin (if any) for the duration
+  /// of the write. Once this returns `Ok`, the final write cycle has completed and the data is committed.
+  /// Returns [`Error::AddressOutOfRange`] without touching the bus if the range does not fit in the device.
+  pub fn write(&mut self, address: usize, data: &[u8], delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    self.with_write_enabled(|eeprom| {
+      for (i, page) in pages(C::PAGE_SIZE, address, data) {
+        eeprom.write_raw(i, page, delay)?;
+      }
+      Ok(())
+    })
   }
 
   /// Read an arbitrary number of bytes from the EEPROM, starting at `address`.
-  /// This function will automatically paginate.
-  pub fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), I2C::Error> {
-    // No need to do this per-page
-    // self.read_raw(address, data)
-
-    // Chunk the read into pages
-    let len = data.len();
-    let mut i = address;
-    while i < (address + data.len()) {
-      let page_offset = i % 32;
-      self.read_raw(i, &mut data[(i - address)..(i - address + (32 - page_offset)).min(len)])?;
-      i += 32 - page_offset;
+  /// The device's address counter rolls over page boundaries, so this is sent as a single sequential read unless
+  /// limited by [`M24Cxx::with_max_read_len`].
+  /// Returns [`Error::AddressOutOfRange`] without touching the bus if the range does not fit in the device.
+  pub fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    let mut i = 0;
+    while i < data.len() {
+      let len = self.read_chunk_len(address + i, data.len() - i);
+      self.read_raw(address + i, &mut data[i..(i + len)])?;
+      i += len;
     }
     Ok(())
   }
+
+  /// Read a single byte from `address` (random address read)
+  pub fn read_byte(&mut self, address: usize) -> Result<u8, Error<I2C::Error>> {
+    let mut buf = [0u8];
+    self.read(address, &mut buf)?;
+    Ok(buf[0])
+  }
+
+  /// Write a single byte to `address` (byte write)
+  pub fn write_byte(&mut self, address: usize, value: u8, delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    self.write(address, &[value], delay)
+  }
+
+  /// Read the byte at the device's internal address counter (current address read), without sending an address.
+  /// The counter points one past the last byte read or written, so successive calls stream through memory.
+  pub fn read_current(&mut self) -> Result<u8, Error<I2C::Error>> {
+    let mut buf = [0u8];
+    self.read_next(&mut buf)?;
+    Ok(buf[0])
+  }
+
+  /// Read sequentially from the device's internal address counter, without sending an address.
+  /// The counter rolls over from the end of the memory array back to the start.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::M24C64;
+  /// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C) -> Result<(), grapple_m24c64::Error<I2C::Error>> {
+  ///
+  /// let mut eeprom = M24C64::new(i2c, 0);
+  /// let mut record = [0u8; 16];
+  /// eeprom.read(0x100, &mut record)?;
+  /// // Continues from 0x110
+  /// eeprom.read_next(&mut record)?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn read_next(&mut self, data: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    self.i2c.read(self.address(), data).map_err(Error::Bus)
+  }
 }
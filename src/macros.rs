@@ -0,0 +1,12 @@
+/// Declare a type alias of the given driver for each part in [`crate::chip`]
+macro_rules! aliases {
+  ($driver:ident) => {
+    aliases!($driver, M24C01, M24C02, M24C04, M24C08, M24C16, M24C32, M24C64, M24128, M24256, M24512, M24M01, M24M02);
+  };
+  ($driver:ident, $($part:ident),*) => {
+    $(
+      #[doc = concat!(stringify!($part), " Driver")]
+      pub type $part<I2C> = $driver<I2C, $crate::chip::$part>;
+    )*
+  };
+}
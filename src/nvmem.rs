@@ -0,0 +1,151 @@
+//! Access through the Linux kernel's `at24` driver, for boards where it already owns the EEPROM.
+//!
+//! The kernel exposes the device as a file, either `/sys/bus/i2c/devices/<bus>-<addr>/eeprom` or
+//! `/sys/bus/nvmem/devices/<name>/nvmem`, and takes care of paging and the write cycle itself. [`Nvmem`] reads and
+//! writes through that file with the same API as the I2C driver, and implements [`EepromDevice`], so wrapped in an
+//! [`EepromStorage`](crate::storage::EepromStorage) the layers built on it ([`record`](crate::record),
+//! [`kv`](crate::kv), ...) run unchanged in Linux userspace.
+//!
+//! Any `Read + Write + Seek` can stand in for the file, which makes a plain temporary file (or a `Cursor`) a
+//! convenient way to test:
+//!
+//! ```
+//! use grapple_m24c64::{device::NoDelay, nvmem, record::{Crc32, Records}, storage::EepromStorage};
+//!
+//! let path = std::env::temp_dir().join("grapple-m24c64-nvmem-doctest.bin");
+//! std::fs::write(&path, [0xFFu8; 8192]).unwrap();
+//!
+//! let mut records = Records::<_, Crc32>::new(EepromStorage::new(nvmem::M24C64::open(&path).unwrap(), NoDelay));
+//! records.write(0x100, 1, b"calibration").unwrap();
+//!
+//! let mut buf = [0u8; 32];
+//! let info = records.read(0x100, &mut buf).unwrap();
+//! assert_eq!(&buf[..info.len], b"calibration");
+//! # std::fs::remove_file(&path).unwrap();
+//! ```
+
+use std::{fs::{File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, marker::PhantomData, path::Path};
+
+use embedded_hal::delay::DelayNs;
+
+use crate::{device::{check_page, check_range}, Chip, EepromDevice, Error};
+
+/// An EEPROM of part `C`, accessed through a file `F` (usually the `at24` sysfs file).
+/// Type aliases are provided for each part, e.g. [`M24C64`].
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{device::NoDelay, nvmem, record::{Crc32, Records}, storage::EepromStorage};
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+///
+/// let mut eeprom = nvmem::M24C64::open("/sys/bus/i2c/devices/1-0050/eeprom")?;
+/// eeprom.write(0xA0, &[0x00, 0x01, 0x02, 0x03])?;
+///
+/// let mut records = Records::<_, Crc32>::new(EepromStorage::new(eeprom, NoDelay));
+/// let mut buf = [0u8; 64];
+/// let info = records.read(0x100, &mut buf)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Nvmem<F, C> {
+  file: F,
+  _chip: PhantomData<C>,
+}
+
+impl<C: Chip> Nvmem<File, C> {
+  /// Open the file exposing the EEPROM, for reading and writing.
+  /// Fails with [`io::ErrorKind::InvalidInput`] if the file is smaller than the part.
+  pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    if file.metadata()?.len() < C::CAPACITY as u64 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "file is smaller than the EEPROM"));
+    }
+    Ok(Self::new(file))
+  }
+}
+
+impl<F, C: Chip> Nvmem<F, C> {
+  /// Size of the memory array, in bytes
+  pub const CAPACITY: usize = C::CAPACITY;
+  /// Size of a single write page, in bytes
+  pub const PAGE_SIZE: usize = C::PAGE_SIZE;
+
+  /// Access the EEPROM through `file`
+  pub fn new(file: F) -> Self {
+    Self { file, _chip: PhantomData }
+  }
+
+  /// Release the underlying file
+  pub fn release(self) -> F {
+    self.file
+  }
+}
+
+impl<F: Read + Write + Seek, C: Chip> Nvmem<F, C> {
+  /// Write an arbitrary number of bytes into the EEPROM, starting at `address`. The kernel splits the write into
+  /// pages and waits out each write cycle, so once this returns `Ok` the data is committed.
+  /// Returns [`Error::AddressOutOfRange`] without touching the file if the range does not fit in the device.
+  pub fn write(&mut self, address: usize, data: &[u8]) -> Result<(), Error<io::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+    self.file.seek(SeekFrom::Start(address as u64)).map_err(Error::Bus)?;
+    self.file.write_all(data).map_err(Error::Bus)?;
+    self.file.flush().map_err(Error::Bus)
+  }
+
+  /// Read an arbitrary number of bytes from the EEPROM, starting at `address`.
+  /// Returns [`Error::AddressOutOfRange`] without touching the file if the range does not fit in the device.
+  pub fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<io::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+    self.file.seek(SeekFrom::Start(address as u64)).map_err(Error::Bus)?;
+    self.file.read_exact(data).map_err(Error::Bus)
+  }
+
+  /// Read a single byte from `address`
+  pub fn read_byte(&mut self, address: usize) -> Result<u8, Error<io::Error>> {
+    let mut buf = [0u8];
+    self.read(address, &mut buf)?;
+    Ok(buf[0])
+  }
+
+  /// Write a single byte to `address`
+  pub fn write_byte(&mut self, address: usize, value: u8) -> Result<(), Error<io::Error>> {
+    self.write(address, &[value])
+  }
+}
+
+impl<F: Read + Write + Seek, C: Chip> EepromDevice for Nvmem<F, C> {
+  type BusError = io::Error;
+
+  const CAPACITY: usize = C::CAPACITY;
+  const PAGE_SIZE: usize = C::PAGE_SIZE;
+
+  fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), Error<Self::BusError>> {
+    Nvmem::read(self, address, data)
+  }
+
+  fn write_page(&mut self, address: usize, data: &[u8]) -> Result<(), Error<Self::BusError>> {
+    check_page(C::CAPACITY, C::PAGE_SIZE, address, data.len())?;
+    Nvmem::write(self, address, data)
+  }
+
+  // The kernel waits out the write cycle before returning from a write
+  fn wait_write_cycle(&mut self, _delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>> {
+    Ok(())
+  }
+
+  fn write(&mut self, address: usize, data: &[u8], _delay: &mut dyn DelayNs) -> Result<(), Error<Self::BusError>> {
+    Nvmem::write(self, address, data)
+  }
+}
+
+/// Declare a type alias for each part in [`crate::chip`], accessed through a [`File`] by default
+macro_rules! nvmem_aliases {
+  ($($part:ident),*) => {
+    $(
+      #[doc = concat!(stringify!($part), " accessed through the kernel")]
+      pub type $part<F = File> = Nvmem<F, crate::chip::$part>;
+    )*
+  };
+}
+
+nvmem_aliases!(M24C01, M24C02, M24C04, M24C08, M24C16, M24C32, M24C64, M24128, M24256, M24512, M24M01, M24M02);
@@ -0,0 +1,339 @@
+//! Splitting one storage into named, bounds-checked partitions.
+//!
+//! A [`PartitionTable`] is declared at compile time from a list of [`Region`]s, and is checked for overlaps and for
+//! fitting within the device when it is built (so a bad table in a `const` fails to compile). A [`Partition`] handle
+//! then gives access to a single region, with addresses relative to its start, and rejects anything that falls
+//! outside of it with [`PartitionError::OutOfBounds`].
+//!
+//! Partitions share the underlying storage through a [`RefCell`], in the same way as `embedded-hal-bus`'s
+//! `RefCellDevice` shares an I2C bus, and implement the `embedded-storage` traits themselves, so each subsystem
+//! (or each higher layer, like [`Records`](crate::record::Records)) can be handed its own partition.
+
+use core::{cell::RefCell, fmt};
+
+use embedded_storage::{nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash}, ReadStorage, Storage};
+
+/// A named, contiguous region of storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+  /// Name of the region, for looking it up in a [`PartitionTable`]
+  pub name: &'static str,
+  /// Absolute address of the first byte of the region
+  pub start: u32,
+  /// Length of the region, in bytes
+  pub len: u32,
+}
+
+impl Region {
+  /// Create a new region of `len` bytes, starting at `start`
+  pub const fn new(name: &'static str, start: u32, len: u32) -> Self {
+    Self { name, start, len }
+  }
+
+  /// Create a new region of `len` bytes, directly following this one
+  pub const fn then(&self, name: &'static str, len: u32) -> Self {
+    Self::new(name, self.end(), len)
+  }
+
+  /// Absolute address one past the last byte of the region
+  pub const fn end(&self) -> u32 {
+    self.start + self.len
+  }
+
+  /// Whether this region shares any bytes with `other`
+  pub const fn overlaps(&self, other: &Region) -> bool {
+    self.start < other.end() && other.start < self.end()
+  }
+}
+
+/// A compile-time table of non-overlapping [`Region`]s
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::partition::{PartitionTable, Region};
+///
+/// const CONFIG: Region = Region::new("config", 0x0000, 0x0400);
+/// const CALIBRATION: Region = CONFIG.then("calibration", 0x0400);
+/// const LOG: Region = CALIBRATION.then("log", 0x1000);
+///
+/// // Fails to compile if the regions overlap, or don't fit in the 8 KiB of an M24C64
+/// const TABLE: PartitionTable<3> = PartitionTable::new([CONFIG, CALIBRATION, LOG], 8192);
+///
+/// assert_eq!(TABLE.find("log"), Some(LOG));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionTable<const N: usize> {
+  regions: [Region; N],
+}
+
+impl<const N: usize> PartitionTable<N> {
+  /// Create a new partition table for a device of `capacity` bytes.
+  ///
+  /// # Panics
+  /// Panics if any two regions overlap, or a region extends past `capacity`. When used in a `const`, this is a
+  /// compile-time error.
+  pub const fn new(regions: [Region; N], capacity: usize) -> Self {
+    let mut i = 0;
+    while i < N {
+      assert!(regions[i].start as usize + regions[i].len as usize <= capacity, "partition does not fit in the device");
+      let mut j = i + 1;
+      while j < N {
+        assert!(!regions[i].overlaps(&regions[j]), "partitions overlap");
+        j += 1;
+      }
+      i += 1;
+    }
+    Self { regions }
+  }
+
+  /// All regions in the table
+  pub const fn regions(&self) -> &[Region; N] {
+    &self.regions
+  }
+
+  /// Look up a region by name
+  pub fn find(&self, name: &str) -> Option<Region> {
+    self.regions.iter().find(|r| r.name == name).copied()
+  }
+}
+
+/// Errors returned by a [`Partition`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError<E> {
+  /// The underlying storage returned an error
+  Storage(E),
+  /// The access falls outside of the partition
+  OutOfBounds,
+}
+
+impl<E: NorFlashError> NorFlashError for PartitionError<E> {
+  fn kind(&self) -> NorFlashErrorKind {
+    match self {
+      PartitionError::Storage(e) => e.kind(),
+      PartitionError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+    }
+  }
+}
+
+impl<E: fmt::Debug> fmt::Display for PartitionError<E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PartitionError::Storage(e) => write!(f, "storage error: {:?}", e),
+      PartitionError::OutOfBounds => write!(f, "access is outside of the partition"),
+    }
+  }
+}
+
+impl<E: fmt::Debug> core::error::Error for PartitionError<E> {}
+
+/// A handle to a single [`Region`] of a shared storage, with addresses relative to the start of the region.
+///
+/// The storage is borrowed only for the duration of each access, so any number of partitions can share it.
+/// For the NOR flash traits, erases are passed through to the storage, so the region should be aligned to its
+/// erase size (a page, for [`EepromStorage`](crate::storage::EepromStorage)).
+///
+/// # Example
+/// ```
+/// use core::cell::RefCell;
+/// use grapple_m24c64::{partition::{Partition, Region}, record::{Crc32, Records}, storage::EepromStorage, M24C64};
+/// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::record::RecordError<grapple_m24c64::partition::PartitionError<grapple_m24c64::Error<I2C::Error>>>> {
+///
+/// const CONFIG: Region = Region::new("config", 0x0000, 0x0400);
+/// const LOG: Region = CONFIG.then("log", 0x1000);
+///
+/// let storage = RefCell::new(EepromStorage::new(M24C64::new(i2c, 0), delay));
+/// let mut config = Records::<_, Crc32>::new(Partition::new(&storage, CONFIG));
+/// let mut log = Partition::new(&storage, LOG);
+///
+/// config.write(0x00, 1, b"calibration")?;
+/// embedded_storage::Storage::write(&mut log, 0x10, b"boot").map_err(grapple_m24c64::record::RecordError::Storage)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Partition<'a, S> {
+  storage: &'a RefCell<S>,
+  region: Region,
+}
+
+impl<'a, S> Clone for Partition<'a, S> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'a, S> Copy for Partition<'a, S> {}
+
+impl<'a, S: ReadStorage> Partition<'a, S> {
+  /// Create a new handle to `region` of `storage`.
+  ///
+  /// # Panics
+  /// Panics if the region extends past the end of the storage, or if the storage is currently borrowed.
+  pub fn new(storage: &'a RefCell<S>, region: Region) -> Self {
+    assert!(region.end() as usize <= storage.borrow().capacity(), "partition does not fit in the storage");
+    Self { storage, region }
+  }
+}
+
+impl<'a, S> Partition<'a, S> {
+  /// The region this partition covers
+  pub fn region(&self) -> Region {
+    self.region
+  }
+
+  /// Translate a relative range into an absolute address, if it lies within the partition
+  fn translate<E>(&self, offset: u32, len: usize) -> Result<u32, PartitionError<E>> {
+    match (offset as usize).checked_add(len) {
+      Some(end) if end <= self.region.len as usize => Ok(self.region.start + offset),
+      _ => Err(PartitionError::OutOfBounds),
+    }
+  }
+}
+
+impl<'a, S: ReadStorage> ReadStorage for Partition<'a, S> {
+  type Error = PartitionError<S::Error>;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    let address = self.translate(offset, bytes.len())?;
+    self.storage.borrow_mut().read(address, bytes).map_err(PartitionError::Storage)
+  }
+
+  fn capacity(&self) -> usize {
+    self.region.len as usize
+  }
+}
+
+impl<'a, S: Storage> Storage for Partition<'a, S> {
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    let address = self.translate(offset, bytes.len())?;
+    self.storage.borrow_mut().write(address, bytes).map_err(PartitionError::Storage)
+  }
+}
+
+impl<'a, S: ErrorType> ErrorType for Partition<'a, S> {
+  type Error = PartitionError<S::Error>;
+}
+
+impl<'a, S: ReadNorFlash> ReadNorFlash for Partition<'a, S> {
+  const READ_SIZE: usize = S::READ_SIZE;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    let address = self.translate(offset, bytes.len())?;
+    ReadNorFlash::read(&mut *self.storage.borrow_mut(), address, bytes).map_err(PartitionError::Storage)
+  }
+
+  fn capacity(&self) -> usize {
+    self.region.len as usize
+  }
+}
+
+impl<'a, S: NorFlash> NorFlash for Partition<'a, S> {
+  const WRITE_SIZE: usize = S::WRITE_SIZE;
+  const ERASE_SIZE: usize = S::ERASE_SIZE;
+
+  fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    if to < from {
+      return Err(PartitionError::OutOfBounds);
+    }
+    let address = self.translate(from, (to - from) as usize)?;
+    self.storage.borrow_mut().erase(address, address + (to - from)).map_err(PartitionError::Storage)
+  }
+
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    let address = self.translate(offset, bytes.len())?;
+    NorFlash::write(&mut *self.storage.borrow_mut(), address, bytes).map_err(PartitionError::Storage)
+  }
+}
+
+impl<'a, S: MultiwriteNorFlash> MultiwriteNorFlash for Partition<'a, S> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{device::{NoDelay, Ram}, storage::EepromStorage};
+
+  type RamStorage = EepromStorage<Ram<1024>, NoDelay>;
+
+  const A: Region = Region::new("a", 0x100, 0x100);
+  const B: Region = A.then("b", 0x80);
+
+  fn storage() -> RefCell<RamStorage> {
+    RefCell::new(EepromStorage::new(Ram::new(), NoDelay))
+  }
+
+  fn memory(storage: RefCell<RamStorage>) -> [u8; 1024] {
+    storage.into_inner().release().0.into_array()
+  }
+
+  #[test]
+  fn addresses_are_relative_to_the_region() {
+    let storage = storage();
+    let mut a = Partition::new(&storage, A);
+    Storage::write(&mut a, 0x10, &[0x01, 0x02, 0x03]).unwrap();
+    NorFlash::write(&mut a, 0xFE, &[0x04, 0x05]).unwrap();
+
+    let mut buf = [0u8; 3];
+    ReadStorage::read(&mut a, 0x10, &mut buf).unwrap();
+    assert_eq!(buf, [0x01, 0x02, 0x03]);
+    assert_eq!(ReadStorage::capacity(&a), 0x100);
+
+    let memory = memory(storage);
+    assert_eq!(&memory[0x110..0x113], &[0x01, 0x02, 0x03]);
+    assert_eq!(&memory[0x1FE..0x200], &[0x04, 0x05]);
+    assert!(memory[..0x100].iter().chain(&memory[0x200..]).all(|&b| b == 0xFF));
+  }
+
+  #[test]
+  fn accesses_past_the_end_are_out_of_bounds() {
+    let storage = storage();
+    let mut a = Partition::new(&storage, A);
+    let mut buf = [0u8; 2];
+
+    assert_eq!(ReadStorage::read(&mut a, 0xFF, &mut buf), Err(PartitionError::OutOfBounds));
+    assert_eq!(ReadNorFlash::read(&mut a, 0x100, &mut buf[..1]), Err(PartitionError::OutOfBounds));
+    assert_eq!(Storage::write(&mut a, 0xFF, &[0x00; 2]), Err(PartitionError::OutOfBounds));
+    assert_eq!(NorFlash::write(&mut a, 0x100, &[0x00]), Err(PartitionError::OutOfBounds));
+    assert_eq!(NorFlash::erase(&mut a, 0xE0, 0x120), Err(PartitionError::OutOfBounds));
+
+    // offset + len overflows a u32
+    assert_eq!(ReadStorage::read(&mut a, u32::MAX, &mut buf), Err(PartitionError::OutOfBounds));
+    assert_eq!(Storage::write(&mut a, u32::MAX, &[0x00; 2]), Err(PartitionError::OutOfBounds));
+    assert_eq!(NorFlash::erase(&mut a, u32::MAX - 0x1F, u32::MAX), Err(PartitionError::OutOfBounds));
+
+    // Nothing reached the storage, not even the part of the access inside the partition
+    assert!(memory(storage).iter().all(|&b| b == 0xFF));
+  }
+
+  #[test]
+  fn erase_with_to_before_from_is_out_of_bounds() {
+    let storage = storage();
+    let mut a = Partition::new(&storage, A);
+    Storage::write(&mut a, 0x20, &[0x00; 0x20]).unwrap();
+
+    assert_eq!(NorFlash::erase(&mut a, 0x40, 0x20), Err(PartitionError::OutOfBounds));
+    NorFlash::erase(&mut a, 0x20, 0x40).unwrap();
+    assert!(memory(storage).iter().all(|&b| b == 0xFF));
+  }
+
+  #[test]
+  fn partitions_share_one_storage() {
+    let storage = storage();
+    let mut a = Partition::new(&storage, A);
+    let mut b = Partition::new(&storage, B);
+
+    Storage::write(&mut a, 0x00, b"first").unwrap();
+    Storage::write(&mut b, 0x00, b"second").unwrap();
+    Storage::write(&mut a, 0x08, b"third").unwrap();
+
+    let mut buf = [0u8; 6];
+    ReadStorage::read(&mut b, 0x00, &mut buf).unwrap();
+    assert_eq!(&buf, b"second");
+    ReadStorage::read(&mut a, 0x00, &mut buf[..5]).unwrap();
+    assert_eq!(&buf[..5], b"first");
+
+    let memory = memory(storage);
+    assert_eq!(&memory[0x100..0x105], b"first");
+    assert_eq!(&memory[0x108..0x10D], b"third");
+    assert_eq!(&memory[0x200..0x206], b"second");
+  }
+}
@@ -0,0 +1,186 @@
+//! Writes that read the memory back, to skip data that is already stored or to verify what was written
+
+use embedded_hal::{delay::DelayNs, digital::OutputPin, i2c::I2c};
+
+use crate::{device::{check_range, pages}, Chip, Error, M24Cxx};
+
+/// First and last index at which `a` and `b` differ
+fn diff_span(a: &[u8], b: &[u8]) -> Option<(usize, usize)> {
+  let first = a.iter().zip(b).position(|(a, b)| a != b)?;
+  let last = a.iter().zip(b).rposition(|(a, b)| a != b)?;
+  Some((first, last))
+}
+
+/// Extend a span of differences with the span of a later piece starting at `offset`
+fn merge_span(span: Option<(usize, usize)>, piece: Option<(usize, usize)>, offset: usize) -> Option<(usize, usize)> {
+  match (span, piece) {
+    (Some((first, _)), Some((_, last))) => Some((first, offset + last)),
+    (None, Some((first, last))) => Some((offset + first, offset + last)),
+    (span, None) => span,
+  }
+}
+
+/// Comparison of data against what is stored, read back in small pieces so no page-sized buffer is needed.
+/// The blocking and async drivers only differ in how each piece is read.
+pub(crate) struct Comparison<'a> {
+  expected: &'a [u8],
+  /// Offset into `expected` of the next piece
+  offset: usize,
+  stored: [u8; 32],
+  changed: Option<(usize, usize)>,
+}
+
+impl<'a> Comparison<'a> {
+  pub(crate) fn new(expected: &'a [u8]) -> Self {
+    Self { expected, offset: 0, stored: [0u8; 32], changed: None }
+  }
+
+  fn piece_len(&self) -> usize {
+    (self.expected.len() - self.offset).min(self.stored.len())
+  }
+
+  /// Offset (into the expected data) of the next piece, and the buffer to read it into.
+  /// Returns `None` once everything has been compared.
+  pub(crate) fn next_read(&mut self) -> Option<(usize, &mut [u8])> {
+    let len = self.piece_len();
+    (len > 0).then(|| (self.offset, &mut self.stored[..len]))
+  }
+
+  /// Compare the piece that was just read, and move on to the next one
+  pub(crate) fn compare_read(&mut self) {
+    let len = self.piece_len();
+    let piece = diff_span(&self.expected[self.offset..(self.offset + len)], &self.stored[..len]);
+    self.changed = merge_span(self.changed, piece, self.offset);
+    self.offset += len;
+  }
+
+  /// First and last offset (into the expected data) that differ, if any
+  pub(crate) fn changed(&self) -> Option<(usize, usize)> {
+    self.changed
+  }
+}
+
+impl<I2C, C, WC> M24Cxx<I2C, C, WC>
+where
+  I2C: I2c,
+  C: Chip,
+  WC: OutputPin
+{
+  /// Compare `data` against what's stored at `address`.
+  /// Returns the first and last offset (into `data`) that differ, if any.
+  fn compare(&mut self, address: usize, data: &[u8]) -> Result<Option<(usize, usize)>, Error<I2C::Error>> {
+    let mut comparison = Comparison::new(data);
+    while let Some((offset, stored)) = comparison.next_read() {
+      self.read(address + offset, stored)?;
+      comparison.compare_read();
+    }
+    Ok(comparison.changed())
+  }
+
+  /// Write an arbitrary number of bytes into the EEPROM, starting at `address`, skipping anything that already
+  /// holds the same data. Each page is read back first, and only the span of it that differs is programmed, saving
+  /// both a write cycle and endurance for every page that is unchanged.
+  /// Returns the number of pages that were actually programmed.
+  pub fn write_if_changed(&mut self, address: usize, data: &[u8], delay: &mut dyn DelayNs) -> Result<usize, Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    self.with_write_enabled(|eeprom| {
+      let mut programmed = 0;
+      for (i, page) in pages(C::PAGE_SIZE, address, data) {
+        if let Some((first, last)) = eeprom.compare(i, page)? {
+          eeprom.write_raw(i + first, &page[first..=last], delay)?;
+          programmed += 1;
+        }
+      }
+      Ok(programmed)
+    })
+  }
+
+  /// Write an arbitrary number of bytes into the EEPROM like [`M24Cxx::write`], reading each page back once its write
+  /// cycle has completed and comparing it against `data`. A page that doesn't match is rewritten up to `retries`
+  /// more times before giving up with [`Error::VerifyFailed`], carrying the first address that still differs.
+  pub fn write_verified(&mut self, address: usize, data: &[u8], retries: u8, delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    check_range(C::CAPACITY, address, data.len())?;
+
+    self.with_write_enabled(|eeprom| {
+      for (i, page) in pages(C::PAGE_SIZE, address, data) {
+        let mut attempts = 0;
+        eeprom.write_raw(i, page, delay)?;
+        while let Some((first, last)) = eeprom.compare(i, page)? {
+          if attempts == retries {
+            return Err(Error::VerifyFailed { address: i + first });
+          }
+          attempts += 1;
+          eeprom.write_raw(i + first, &page[first..=last], delay)?;
+        }
+      }
+      Ok(())
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use embedded_hal::i2c::{ErrorType, I2c, Operation};
+
+  use crate::{sim::{self, NoDelay}, Error, M24C64};
+
+  /// A device with a cell whose lowest bit is stuck at 0
+  struct StuckBit(sim::M24C64, usize);
+
+  impl ErrorType for StuckBit {
+    type Error = sim::SimError;
+  }
+
+  impl I2c for StuckBit {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+      let result = self.0.transaction(address, operations);
+      self.0.memory_mut()[self.1] &= !0x01;
+      result
+    }
+  }
+
+  #[test]
+  fn write_if_changed_skips_unchanged_pages() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    let mut data = [0u8; 100];
+    data.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+
+    // 0x10..0x74 touches pages 0 to 3
+    assert_eq!(eeprom.write_if_changed(0x10, &data, &mut NoDelay), Ok(4));
+    assert_eq!(eeprom.write_if_changed(0x10, &data, &mut NoDelay), Ok(0));
+
+    data[50] ^= 0xFF;
+    data[55] ^= 0xFF;
+    assert_eq!(eeprom.write_if_changed(0x10, &data, &mut NoDelay), Ok(1));
+
+    let device = eeprom.release();
+    assert_eq!(&device.memory()[0x10..0x74], &data);
+    assert_eq!(device.page_write_cycles()[..5], [1, 1, 2, 1, 0]);
+  }
+
+  #[test]
+  fn write_if_changed_rejects_out_of_range() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    assert_eq!(eeprom.write_if_changed(8190, &[0; 4], &mut NoDelay), Err(Error::AddressOutOfRange));
+  }
+
+  #[test]
+  fn write_verified_writes_and_checks() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    eeprom.write_verified(0x1C, &[0x5A; 40], 0, &mut NoDelay).unwrap();
+    assert_eq!(&eeprom.release().memory()[0x1C..0x44], &[0x5A; 40]);
+  }
+
+  #[test]
+  fn write_verified_retries_then_fails() {
+    let mut eeprom = M24C64::new(StuckBit(sim::M24C64::new(0), 0x45), 0);
+    let result = eeprom.write_verified(0x30, &[0xFF; 32], 2, &mut NoDelay);
+    assert_eq!(result, Err(Error::VerifyFailed { address: 0x45 }));
+
+    // The first page verified, the second was written once and retried twice
+    let device = eeprom.release().0;
+    assert_eq!(device.page_write_cycles()[1..3], [1, 3]);
+    assert_eq!(device.memory()[0x44], 0xFF);
+  }
+}
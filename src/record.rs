@@ -0,0 +1,260 @@
+//! CRC-protected records, for detecting corrupted (e.g. torn by a brown-out) data.
+//!
+//! Each record is stored as a header followed by its payload. The header holds the payload length, a
+//! user-defined version, and a checksum over both the header fields and the payload:
+//!
+//! | Offset | Size | Field                 |
+//! |--------|------|-----------------------|
+//! | 0      | 2    | Payload length (LE)   |
+//! | 2      | 2    | Version (LE)          |
+//! | 4      | 4    | Checksum (LE)         |
+//! | 8      | len  | Payload               |
+//!
+//! Records are built on the `embedded-storage` [`Storage`] trait, so they can be used with
+//! [`EepromStorage`](crate::storage::EepromStorage) or any other storage. No allocator is required.
+
+use core::{fmt, marker::PhantomData};
+
+use embedded_storage::Storage;
+
+/// Size of the record header, in bytes
+pub const HEADER_SIZE: usize = 8;
+
+/// Checksum algorithm used to protect records
+pub trait Checksum {
+  /// Running state of the checksum
+  type State;
+
+  /// Start a new checksum
+  fn begin() -> Self::State;
+  /// Add `data` to the checksum
+  fn update(state: &mut Self::State, data: &[u8]);
+  /// Finish the checksum
+  fn finish(state: Self::State) -> u32;
+
+  /// Compute the checksum over the concatenation of `parts`
+  fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut state = Self::begin();
+    for part in parts {
+      Self::update(&mut state, part);
+    }
+    Self::finish(state)
+  }
+}
+
+static CRC_32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+static CRC_16: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+
+/// CRC-32 (ISO-HDLC, as used by Ethernet and zlib)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Crc32;
+
+impl Checksum for Crc32 {
+  type State = crc::Digest<'static, u32>;
+
+  fn begin() -> Self::State {
+    CRC_32.digest()
+  }
+
+  fn update(state: &mut Self::State, data: &[u8]) {
+    state.update(data)
+  }
+
+  fn finish(state: Self::State) -> u32 {
+    state.finalize()
+  }
+}
+
+/// CRC-16 (IBM-3740, also known as CCITT-FALSE). Stored zero-extended in the 4-byte checksum field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Crc16;
+
+impl Checksum for Crc16 {
+  type State = crc::Digest<'static, u16>;
+
+  fn begin() -> Self::State {
+    CRC_16.digest()
+  }
+
+  fn update(state: &mut Self::State, data: &[u8]) {
+    state.update(data)
+  }
+
+  fn finish(state: Self::State) -> u32 {
+    state.finalize() as u32
+  }
+}
+
+/// Errors returned by the record layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError<E> {
+  /// The underlying storage returned an error
+  Storage(E),
+  /// The record's header or checksum is invalid (e.g. the record was torn by a power loss, or never written)
+  Corrupted,
+  /// The buffer given is too small to hold the record's payload
+  BufferTooSmall {
+    /// Length of the stored payload
+    required: usize
+  },
+  /// The payload is larger than a record can hold, or doesn't fit in the storage
+  TooLarge,
+}
+
+impl<E: fmt::Debug> fmt::Display for RecordError<E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RecordError::Storage(e) => write!(f, "storage error: {:?}", e),
+      RecordError::Corrupted => write!(f, "record is corrupted"),
+      RecordError::BufferTooSmall { required } => write!(f, "buffer too small, record needs {} bytes", required),
+      RecordError::TooLarge => write!(f, "record is too large"),
+    }
+  }
+}
+
+impl<E: fmt::Debug> core::error::Error for RecordError<E> {}
+
+/// Header of a valid record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordInfo {
+  /// Version given when the record was written
+  pub version: u16,
+  /// Length of the payload, in bytes
+  pub len: usize,
+}
+
+/// Reads and writes CRC-protected records on top of a [`Storage`], with the checksum algorithm `K`.
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{record::{Crc32, Records}, storage::EepromStorage, M24C64};
+/// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::record::RecordError<grapple_m24c64::Error<I2C::Error>>> {
+///
+/// let mut records = Records::<_, Crc32>::new(EepromStorage::new(M24C64::new(i2c, 0), delay));
+/// records.write(0x100, 1, b"calibration")?;
+///
+/// let mut buf = [0u8; 32];
+/// let info = records.read(0x100, &mut buf)?;
+/// assert_eq!(&buf[..info.len], b"calibration");
+/// # Ok(())
+/// # }
+/// ```
+pub struct Records<S, K = Crc32> {
+  storage: S,
+  _checksum: PhantomData<K>
+}
+
+impl<S, K> Records<S, K> {
+  /// Create a record layer on top of `storage`
+  pub fn new(storage: S) -> Self {
+    Self { storage, _checksum: PhantomData }
+  }
+
+  /// Get a reference to the underlying storage
+  pub fn storage(&mut self) -> &mut S {
+    &mut self.storage
+  }
+
+  /// Release the underlying storage
+  pub fn release(self) -> S {
+    self.storage
+  }
+
+  /// Number of bytes taken up by a record with a payload of `len` bytes
+  pub const fn record_size(len: usize) -> usize {
+    HEADER_SIZE + len
+  }
+}
+
+/// Encode the header of a record, computing its checksum with `K`
+pub(crate) fn encode_header<K: Checksum>(version: u16, payload: &[u8]) -> [u8; HEADER_SIZE] {
+  let mut header = [0u8; HEADER_SIZE];
+  header[0..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+  header[2..4].copy_from_slice(&version.to_le_bytes());
+  let checksum = K::checksum(&[&header[0..4], payload]);
+  header[4..8].copy_from_slice(&checksum.to_le_bytes());
+  header
+}
+
+/// Decode the length, version and checksum from a record header
+pub(crate) fn decode_header(header: &[u8; HEADER_SIZE]) -> (usize, u16, u32) {
+  let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+  let version = u16::from_le_bytes([header[2], header[3]]);
+  let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+  (len, version, checksum)
+}
+
+impl<S: Storage, K: Checksum> Records<S, K> {
+  /// Write a record with the given `version` and `payload` at `address`
+  pub fn write(&mut self, address: u32, version: u16, payload: &[u8]) -> Result<(), RecordError<S::Error>> {
+    if payload.len() > u16::MAX as usize || address as usize + Self::record_size(payload.len()) > self.storage.capacity() {
+      return Err(RecordError::TooLarge);
+    }
+
+    // The header goes last, so an interrupted write never leaves a valid header describing a stale payload
+    let header = encode_header::<K>(version, payload);
+    self.storage.write(address + HEADER_SIZE as u32, payload).map_err(RecordError::Storage)?;
+    self.storage.write(address, &header).map_err(RecordError::Storage)
+  }
+
+  /// Read the record at `address` into `buf`, verifying its checksum. The payload occupies `buf[..info.len]`.
+  /// Returns [`RecordError::Corrupted`] if the record is invalid.
+  pub fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<RecordInfo, RecordError<S::Error>> {
+    let mut header = [0u8; HEADER_SIZE];
+    self.storage.read(address, &mut header).map_err(RecordError::Storage)?;
+    let (len, version, checksum) = decode_header(&header);
+
+    if address as usize + Self::record_size(len) > self.storage.capacity() {
+      return Err(RecordError::Corrupted);
+    }
+    if len > buf.len() {
+      return Err(RecordError::BufferTooSmall { required: len });
+    }
+
+    let payload = &mut buf[..len];
+    self.storage.read(address + HEADER_SIZE as u32, payload).map_err(RecordError::Storage)?;
+    if K::checksum(&[&header[0..4], payload]) != checksum {
+      return Err(RecordError::Corrupted);
+    }
+
+    Ok(RecordInfo { version, len })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sim::{self, fixture::{self, lose_power, release, SimStorage}};
+
+  fn records(device: sim::M24C64) -> Records<SimStorage> {
+    Records::new(fixture::storage(device))
+  }
+
+  #[test]
+  fn torn_write_reads_as_corrupted() {
+    // The payload (0x108..0x148) takes three page writes, and the header a fourth
+    for writes in 0..4 {
+      let mut records = records(sim::M24C64::new(0).with_seed(writes as u64));
+      records.write(0x100, 1, &[0x11; 64]).unwrap();
+
+      let device = release(records.release());
+      let (mut records, _) = lose_power(device, writes, Records::<_>::new, Records::release, |records| {
+        assert!(records.write(0x100, 2, &[0x22; 64]).is_err());
+      });
+      assert_eq!(records.read(0x100, &mut [0u8; 64]), Err(RecordError::Corrupted), "torn after {} writes", writes);
+    }
+  }
+
+  #[test]
+  fn flipped_bit_reads_as_corrupted() {
+    let mut records = records(sim::M24C64::new(0));
+    records.write(0x100, 1, b"calibration").unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(records.read(0x100, &mut buf), Ok(RecordInfo { version: 1, len: 11 }));
+
+    let mut device = release(records.release());
+    device.flip_bit(0x100 + HEADER_SIZE + 4, 3);
+    let mut records = self::records(device);
+    assert_eq!(records.read(0x100, &mut buf), Err(RecordError::Corrupted));
+  }
+}
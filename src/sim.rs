@@ -0,0 +1,696 @@
+//! A simulated M24Cxx device, implementing [`I2c`], for testing on the host without hardware.
+//!
+//! The simulation models the behaviour of the real device on the bus, rather than just storing bytes:
+//!
+//! - The memory address is sent as one or two bytes (with the block select bits of larger parts carried in the
+//!   device address), and the device keeps an internal address counter between transactions.
+//! - Bytes written past the end of a page wrap around to the start of the same page.
+//! - Sequential reads roll over from the end of the memory array back to address 0.
+//! - Written data is latched, and only committed once the write is ended with a STOP (a repeated START aborts it).
+//!   The device then NACKs its address for the duration of its internal write cycle, modelled as a number of
+//!   address phases rather than time (see [`Eeprom::with_write_cycle_polls`]).
+//! - The device only responds to the address selected by its E pins.
+//! - For parts with an Identification Page, the page can be written, read and locked. Once locked, data bytes
+//!   written to it are NACKed.
+//!
+//! Faults can also be injected, to test how code built on the driver copes with them. Bus errors (NACKs, arbitration
+//! loss) can be injected on chosen transactions, power can be lost part way through a page write (tearing it),
+//! and bits can be flipped to mimic retention failures. Anything random is drawn from a seeded generator
+//! ([`Eeprom::with_seed`]), so tests are deterministic. The number of write cycles seen by each page is also
+//! counted, for checking wear leveling.
+//!
+//! # Example
+//! ```
+//! use grapple_m24c64::{sim::{self, NoDelay}, M24C64};
+//!
+//! let mut eeprom = M24C64::new(sim::M24C64::new(0b010), 0b010);
+//! eeprom.write(0x1E, &[0x00, 0x01, 0x02, 0x03, 0x04], &mut NoDelay).unwrap();
+//!
+//! let mut buf = [0u8; 5];
+//! eeprom.read(0x1E, &mut buf).unwrap();
+//! assert_eq!(buf, [0x00, 0x01, 0x02, 0x03, 0x04]);
+//!
+//! let device = eeprom.release();
+//! assert_eq!(&device.memory()[0x1E..0x23], &[0x00, 0x01, 0x02, 0x03, 0x04]);
+//! ```
+//!
+//! Losing power part way through a write:
+//! ```
+//! use grapple_m24c64::{sim::{self, NoDelay}, Error, M24C64};
+//!
+//! let mut device = sim::M24C64::new(0).with_seed(42);
+//! device.lose_power_after(1);
+//!
+//! let mut eeprom = M24C64::new(device, 0);
+//! eeprom.write(0x00, &[0xAA; 64], &mut NoDelay).unwrap_err();
+//!
+//! // The first page was written, the second was torn, and the device has gone quiet
+//! let mut device = eeprom.release();
+//! assert!(!device.is_powered());
+//! assert_eq!(&device.memory()[..32], &[0xAA; 32]);
+//! assert_eq!(device.page_write_cycles()[..3], [1, 1, 0]);
+//!
+//! device.power_on();
+//! let mut eeprom = M24C64::new(device, 0);
+//! eeprom.read(0x00, &mut [0u8; 64]).unwrap();
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+use crate::chip::{self, Chip};
+
+// The simulated write cycle is counted in address polls, so there is nothing to wait for
+pub use crate::device::NoDelay;
+
+/// Largest page size of any part in the family
+const MAX_PAGE_SIZE: usize = 256;
+/// Largest number of pages of any part in the family
+const MAX_PAGES: usize = 1024;
+/// Largest number of bus errors that can be scheduled at once
+const MAX_INJECTED_ERRORS: usize = 8;
+
+/// Error returned by the simulated device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimError(pub ErrorKind);
+
+impl i2c::Error for SimError {
+  fn kind(&self) -> ErrorKind {
+    self.0
+  }
+}
+
+/// xorshift64* generator, for reproducible faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rng(u64);
+
+impl Rng {
+  fn new(seed: u64) -> Self {
+    // The all-zero state is a fixed point
+    Self(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+  }
+
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  /// A value in `0..bound`
+  fn below(&mut self, bound: usize) -> usize {
+    (self.next() % bound as u64) as usize
+  }
+}
+
+/// What a transaction is addressed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+  /// The memory array, with the given block select bits
+  Memory(usize),
+  /// The Identification Page
+  IdPage,
+}
+
+/// What the data bytes of a write go to, once the memory address has been received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Destination {
+  Memory,
+  IdPage,
+  IdLock,
+}
+
+/// A write in progress, latched until the STOP condition
+struct Latch {
+  address: [u8; 2],
+  received: usize,
+  destination: Option<Destination>,
+  /// Start of the page being written
+  base: usize,
+  /// Offset within the page of the next byte
+  offset: usize,
+  data: [u8; MAX_PAGE_SIZE],
+  written: [bool; MAX_PAGE_SIZE],
+  count: usize,
+}
+
+/// A simulated M24Cxx device of part `C`, with an `N` byte memory array (which must match `C::CAPACITY`).
+/// Type aliases are provided for each part, e.g. [`M24C64`].
+///
+/// # Example
+/// ```
+/// use embedded_hal::i2c::I2c;
+/// use grapple_m24c64::sim;
+///
+/// let mut device = sim::M24C64::new(0).with_write_cycle_polls(2);
+///
+/// // Two bytes from the end of a page, so the last two wrap around to its start
+/// device.write(0x50, &[0x00, 0x1E, 0x01, 0x02, 0x03, 0x04]).unwrap();
+/// assert_eq!(&device.memory()[0x1E..0x20], &[0x01, 0x02]);
+/// assert_eq!(&device.memory()[0x00..0x02], &[0x03, 0x04]);
+///
+/// // Busy with the write cycle
+/// assert!(device.write(0x50, &[]).is_err());
+/// assert!(device.write(0x50, &[]).is_err());
+/// assert!(device.write(0x50, &[]).is_ok());
+/// ```
+pub struct Eeprom<C, const N: usize> {
+  memory: [u8; N],
+  id_page: [u8; MAX_PAGE_SIZE],
+  id_locked: bool,
+  e_addr: u8,
+  /// Internal address counter
+  pointer: usize,
+  /// Address counter within the Identification Page
+  id_pointer: usize,
+  /// Remaining address phases to NACK before the write cycle completes
+  busy: u32,
+  write_cycle_polls: u32,
+  /// Number of transactions seen so far
+  transactions: u32,
+  /// Bus errors to return on the given transactions
+  injected: [Option<(u32, ErrorKind)>; MAX_INJECTED_ERRORS],
+  /// Number of write cycles to complete before losing power during the next one
+  power_loss: Option<u32>,
+  powered: bool,
+  /// Number of write cycles seen by each page of the memory array
+  write_cycles: [u32; MAX_PAGES],
+  rng: Rng,
+  _chip: PhantomData<C>,
+}
+
+impl<C: Chip, const N: usize> Eeprom<C, N> {
+  /// Create a new, blank (all `0xFF`) device, with its E pins tied to `e_addr` (`0b0000_0 E2 E1 E0`).
+  ///
+  /// # Panics
+  /// Panics if `N` doesn't match the capacity of the part, or `e_addr` is more than 3 bits.
+  /// The random generator is seeded with a fixed value, see [`Eeprom::with_seed`].
+  pub fn new(e_addr: u8) -> Self {
+    assert!(N == C::CAPACITY, "memory array size does not match the part");
+    assert!(e_addr <= 0b111, "E-pin address must be 3 bits");
+    Self {
+      memory: [0xFF; N],
+      id_page: [0xFF; MAX_PAGE_SIZE],
+      id_locked: false,
+      e_addr,
+      pointer: 0,
+      id_pointer: 0,
+      busy: 0,
+      write_cycle_polls: 3,
+      transactions: 0,
+      injected: [None; MAX_INJECTED_ERRORS],
+      power_loss: None,
+      powered: true,
+      write_cycles: [0; MAX_PAGES],
+      rng: Rng::new(0),
+      _chip: PhantomData
+    }
+  }
+
+  /// Set how many address phases the device NACKs after each write, standing in for the duration of its internal
+  /// write cycle. Defaults to 3.
+  pub fn with_write_cycle_polls(mut self, polls: u32) -> Self {
+    self.write_cycle_polls = polls;
+    self
+  }
+
+  /// Seed the generator used for torn writes and random bit flips
+  pub fn with_seed(mut self, seed: u64) -> Self {
+    self.rng = Rng::new(seed);
+    self
+  }
+
+  /// Contents of the memory array
+  pub fn memory(&self) -> &[u8; N] {
+    &self.memory
+  }
+
+  /// Contents of the memory array, for setting up or inspecting a test without going through the bus
+  pub fn memory_mut(&mut self) -> &mut [u8; N] {
+    &mut self.memory
+  }
+
+  /// Contents of the Identification Page
+  pub fn id_page(&self) -> &[u8] {
+    &self.id_page[..C::PAGE_SIZE]
+  }
+
+  /// Whether the Identification Page has been locked
+  pub fn is_id_page_locked(&self) -> bool {
+    self.id_locked
+  }
+
+  /// Whether the device is still in its internal write cycle (and will NACK its address)
+  pub fn is_busy(&self) -> bool {
+    self.busy > 0
+  }
+
+  /// Number of write cycles each page of the memory array has seen, including torn ones
+  pub fn page_write_cycles(&self) -> &[u32] {
+    &self.write_cycles[..(C::CAPACITY / C::PAGE_SIZE)]
+  }
+
+  /// Number of transactions the device has seen so far, i.e. the index of the next one
+  pub fn transactions(&self) -> u32 {
+    self.transactions
+  }
+
+  /// Fail transaction number `transaction` (counting from 0, see [`Eeprom::transactions`]) with a bus error of
+  /// `kind`, without it reaching the device.
+  ///
+  /// # Panics
+  /// Panics if too many errors are already scheduled.
+  pub fn inject_error(&mut self, transaction: u32, kind: ErrorKind) {
+    let slot = self.injected.iter_mut().find(|i| i.is_none()).expect("too many injected errors");
+    *slot = Some((transaction, kind));
+  }
+
+  /// Lose power during a page write to the memory array, after `writes` more have completed.
+  /// The page write is torn: each of its bytes is left with either the old data, the new data, or garbage. The
+  /// device then stops responding until [`Eeprom::power_on`] is called.
+  pub fn lose_power_after(&mut self, writes: u32) {
+    self.power_loss = Some(writes);
+  }
+
+  /// Whether the device is powered (and responding on the bus)
+  pub fn is_powered(&self) -> bool {
+    self.powered
+  }
+
+  /// Restore power after a power loss. The device comes back idle, with its address counters reset.
+  pub fn power_on(&mut self) {
+    self.powered = true;
+    self.busy = 0;
+    self.pointer = 0;
+    self.id_pointer = 0;
+  }
+
+  /// Flip bit `bit` of the byte at `address`
+  pub fn flip_bit(&mut self, address: usize, bit: u8) {
+    self.memory[address] ^= 1 << (bit % 8);
+  }
+
+  /// Flip `count` randomly chosen bits in the memory array, as a retention failure would
+  pub fn flip_random_bits(&mut self, count: usize) {
+    for _ in 0..count {
+      let bit = self.rng.below(N * 8);
+      self.memory[bit / 8] ^= 1 << (bit % 8);
+    }
+  }
+
+  /// Whether the part has an Identification Page (the 2-byte address parts without block select bits)
+  fn has_id_page() -> bool {
+    C::ADDRESS_BYTES == 2 && C::BLOCK_BITS == 0
+  }
+
+  /// Work out which part of the device, if any, `address` selects
+  fn decode(&self, address: u8) -> Option<Target> {
+    let block_mask = ((1u32 << C::BLOCK_BITS) - 1) as u8;
+    let e_mask = 0b111 & !block_mask;
+    if address & 0x78 == 0x50 && address & e_mask == self.e_addr & e_mask {
+      Some(Target::Memory((address & block_mask) as usize))
+    } else if Self::has_id_page() && address == (0x58 | self.e_addr) {
+      Some(Target::IdPage)
+    } else {
+      None
+    }
+  }
+
+  /// Receive one byte of a write, following the memory address
+  fn receive(&mut self, target: Target, latch: &mut Latch, byte: u8) -> Result<(), SimError> {
+    if latch.received < C::ADDRESS_BYTES {
+      latch.address[latch.received] = byte;
+      latch.received += 1;
+      if latch.received == C::ADDRESS_BYTES {
+        self.set_pointer(target, latch);
+      }
+      return Ok(());
+    }
+
+    match latch.destination {
+      Some(Destination::IdPage | Destination::IdLock) if self.id_locked => {
+        Err(SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)))
+      },
+      Some(Destination::IdLock) => {
+        // Only the lock bit is significant, nothing is stored
+        latch.data[0] |= byte & 0x02;
+        latch.count += 1;
+        Ok(())
+      },
+      _ => {
+        latch.data[latch.offset] = byte;
+        latch.written[latch.offset] = true;
+        latch.offset = (latch.offset + 1) % C::PAGE_SIZE;
+        latch.count += 1;
+        Ok(())
+      }
+    }
+  }
+
+  /// Load the address counter from the memory address bytes of a write
+  fn set_pointer(&mut self, target: Target, latch: &mut Latch) {
+    let address = latch.address[..C::ADDRESS_BYTES].iter().fold(0usize, |a, &b| (a << 8) | b as usize);
+    match target {
+      Target::Memory(block) => {
+        self.pointer = ((block << (8 * C::ADDRESS_BYTES)) | address) % C::CAPACITY;
+        latch.destination = Some(Destination::Memory);
+        latch.base = self.pointer - self.pointer % C::PAGE_SIZE;
+        latch.offset = self.pointer % C::PAGE_SIZE;
+      },
+      // A10 selects the lock register instead of the page itself
+      Target::IdPage if address & 0x0400 != 0 => {
+        latch.destination = Some(Destination::IdLock);
+        latch.data[0] = 0;
+      },
+      Target::IdPage => {
+        self.id_pointer = address % C::PAGE_SIZE;
+        latch.destination = Some(Destination::IdPage);
+        latch.offset = self.id_pointer;
+      },
+    }
+  }
+
+  /// Send the next byte of a read, advancing the address counter
+  fn transmit(&mut self, target: Target) -> u8 {
+    match target {
+      Target::Memory(_) => {
+        let byte = self.memory[self.pointer];
+        self.pointer = (self.pointer + 1) % C::CAPACITY;
+        byte
+      },
+      Target::IdPage => {
+        let byte = self.id_page[self.id_pointer];
+        self.id_pointer = (self.id_pointer + 1) % C::PAGE_SIZE;
+        byte
+      },
+    }
+  }
+
+  /// Commit a latched write on STOP, and start the internal write cycle
+  fn commit(&mut self, latch: &Latch) {
+    if latch.count == 0 {
+      return;
+    }
+
+    match latch.destination {
+      Some(Destination::Memory) => {
+        let page = latch.base / C::PAGE_SIZE;
+        self.write_cycles[page] = self.write_cycles[page].saturating_add(1);
+
+        let torn = self.power_loss == Some(0);
+        self.power_loss = self.power_loss.map(|n| n.wrapping_sub(1)).filter(|_| !torn);
+
+        for (offset, _) in latch.written[..C::PAGE_SIZE].iter().enumerate().filter(|(_, w)| **w) {
+          let cell = &mut self.memory[latch.base + offset];
+          *cell = match torn {
+            false => latch.data[offset],
+            true => match self.rng.below(3) {
+              0 => *cell,
+              1 => latch.data[offset],
+              _ => self.rng.next() as u8,
+            }
+          };
+        }
+        self.pointer = latch.base + latch.offset;
+
+        if torn {
+          self.powered = false;
+          return;
+        }
+      },
+      Some(Destination::IdPage) => {
+        for (offset, _) in latch.written[..C::PAGE_SIZE].iter().enumerate().filter(|(_, w)| **w) {
+          self.id_page[offset] = latch.data[offset];
+        }
+        self.id_pointer = latch.offset;
+      },
+      Some(Destination::IdLock) => self.id_locked |= latch.data[0] != 0,
+      None => (),
+    }
+    self.busy = self.write_cycle_polls;
+  }
+}
+
+impl<C, const N: usize> ErrorType for Eeprom<C, N> {
+  type Error = SimError;
+}
+
+impl<C: Chip, const N: usize> I2c for Eeprom<C, N> {
+  fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+    let nack_address = SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+
+    let index = self.transactions;
+    self.transactions = self.transactions.wrapping_add(1);
+    if let Some(slot) = self.injected.iter_mut().find(|i| i.is_some_and(|(t, _)| t == index)) {
+      let (_, kind) = slot.take().unwrap();
+      return Err(SimError(kind));
+    }
+
+    if !self.powered {
+      return Err(nack_address);
+    }
+    let target = self.decode(address).ok_or(nack_address)?;
+
+    if operations.is_empty() && self.busy > 0 {
+      self.busy -= 1;
+      return Err(nack_address);
+    }
+
+    let mut latch = None;
+    let mut i = 0;
+    while i < operations.len() {
+      // Address phase, at the START and at each repeated START (which also aborts any write in progress)
+      if self.busy > 0 {
+        self.busy -= 1;
+        return Err(nack_address);
+      }
+      latch = None;
+
+      // Adjacent operations of the same kind are one transfer, without a repeated START
+      if let Operation::Write(_) = operations[i] {
+        let mut l = Latch {
+          address: [0; 2], received: 0, destination: None, base: 0, offset: 0,
+          data: [0; MAX_PAGE_SIZE], written: [false; MAX_PAGE_SIZE], count: 0
+        };
+        while let Some(Operation::Write(bytes)) = operations.get(i) {
+          for &byte in bytes.iter() {
+            self.receive(target, &mut l, byte)?;
+          }
+          i += 1;
+        }
+        latch = Some(l);
+      } else {
+        while let Some(Operation::Read(buf)) = operations.get_mut(i) {
+          for byte in buf.iter_mut() {
+            *byte = self.transmit(target);
+          }
+          i += 1;
+        }
+      }
+    }
+
+    // STOP
+    if let Some(latch) = latch {
+      self.commit(&latch);
+    }
+    Ok(())
+  }
+}
+
+/// Declare a simulated device type alias for each part in [`crate::chip`]
+macro_rules! sim_aliases {
+  ($($part:ident),*) => {
+    $(
+      #[doc = concat!("Simulated ", stringify!($part))]
+      pub type $part = Eeprom<chip::$part, { <chip::$part as Chip>::CAPACITY }>;
+    )*
+  };
+}
+
+sim_aliases!(M24C01, M24C02, M24C04, M24C08, M24C16, M24C32, M24C64, M24128, M24256, M24512, M24M01, M24M02);
+
+/// Shared setup for testing the storage layers against a simulated device
+#[cfg(test)]
+pub(crate) mod fixture {
+  use crate::{device::NoDelay, storage::EepromStorage};
+
+  /// Storage over a simulated M24C64, with E pins 0
+  pub(crate) type SimStorage = EepromStorage<crate::M24C64<super::M24C64>, NoDelay>;
+
+  /// Wrap `device` in a driver and storage
+  pub(crate) fn storage(device: super::M24C64) -> SimStorage {
+    EepromStorage::new(crate::M24C64::new(device, 0), NoDelay)
+  }
+
+  /// Take the simulated device back out of `storage`
+  pub(crate) fn release(storage: SimStorage) -> super::M24C64 {
+    storage.release().0.release()
+  }
+
+  /// Mount a layer over `device`, run `op` on it with power lost after `writes` page writes, then power the device
+  /// back on and mount the layer again. Returns the remounted layer, and what `op` returned.
+  pub(crate) fn lose_power<L, T>(
+    mut device: super::M24C64, writes: u32,
+    mount: impl Fn(SimStorage) -> L, unmount: impl Fn(L) -> SimStorage, op: impl FnOnce(&mut L) -> T
+  ) -> (L, T) {
+    device.lose_power_after(writes);
+    let mut layer = mount(storage(device));
+    let result = op(&mut layer);
+
+    let mut device = release(unmount(layer));
+    device.power_on();
+    (mount(storage(device)), result)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Error, WritePolling};
+
+  type Driver<C, const N: usize> = crate::M24Cxx<Eeprom<C, N>, C>;
+
+  #[test]
+  fn page_write_wraps_within_the_page() {
+    let mut device = M24C64::new(0).with_write_cycle_polls(0);
+    device.write(0x50, &[0x00, 0x3C, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]).unwrap();
+    assert_eq!(&device.memory()[0x3C..0x40], &[0x01, 0x02, 0x03, 0x04]);
+    assert_eq!(&device.memory()[0x20..0x22], &[0x05, 0x06]);
+    assert_eq!(device.memory()[0x40], 0xFF);
+    assert_eq!(device.page_write_cycles()[..3], [0, 1, 0]);
+  }
+
+  #[test]
+  fn sequential_read_rolls_over_the_end_of_memory() {
+    let mut eeprom = crate::M24C64::new(M24C64::new(0), 0);
+    eeprom.write(0x1FFE, &[0xAA, 0xBB], &mut NoDelay).unwrap();
+    eeprom.write(0x0000, &[0xCC, 0xDD], &mut NoDelay).unwrap();
+
+    let mut buf = [0u8; 2];
+    eeprom.read(0x1FFE, &mut buf).unwrap();
+    eeprom.read_next(&mut buf).unwrap();
+    assert_eq!(buf, [0xCC, 0xDD]);
+  }
+
+  #[test]
+  fn read_next_continues_from_the_last_access() {
+    let mut eeprom = crate::M24C64::new(M24C64::new(0), 0);
+    let data: [u8; 32] = core::array::from_fn(|i| i as u8);
+    eeprom.write(0x100, &data, &mut NoDelay).unwrap();
+
+    let mut buf = [0u8; 8];
+    eeprom.read(0x100, &mut buf).unwrap();
+    eeprom.read_next(&mut buf).unwrap();
+    assert_eq!(buf, data[8..16]);
+    assert_eq!(eeprom.read_current(), Ok(16));
+
+    // A write leaves the counter one past the last byte written
+    eeprom.write(0x200, &[1, 2, 3], &mut NoDelay).unwrap();
+    assert_eq!(eeprom.read_current(), Ok(0xFF));
+  }
+
+  #[test]
+  fn wrong_e_pins_are_not_acknowledged() {
+    let mut eeprom = crate::M24C64::new(M24C64::new(0b010), 0b011);
+    let nack = Err(Error::Bus(SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))));
+    assert_eq!(eeprom.read(0, &mut [0u8; 4]), nack);
+    assert_eq!(eeprom.write(0, &[0u8; 4], &mut NoDelay), nack);
+    // Reported straight away, without polling for a write cycle that never started
+    assert_eq!(eeprom.i2c_mut().transactions(), 2);
+    assert_eq!(eeprom.release().memory(), &[0xFF; 8192]);
+  }
+
+  #[test]
+  fn checked_constructors_reject_invalid_e_pins() {
+    let eeprom = crate::M24C64::try_new(M24C64::new(0b101), 0b101).unwrap();
+    assert_eq!(eeprom.address(), 0x55);
+    assert!(matches!(crate::M24C64::try_new(M24C64::new(0), 0x50), Err(Error::InvalidDeviceAddress)));
+
+    // E0 is A8 on the M24C04
+    let e_pins = crate::EPins::new(false, false, true);
+    assert!(matches!(Driver::<chip::M24C04, 512>::try_with_e_pins(M24C04::new(0), e_pins), Err(Error::InvalidDeviceAddress)));
+  }
+
+  #[test]
+  fn block_select_bits_address_the_upper_half() {
+    let mut eeprom = Driver::<chip::M24C04, 512>::new(M24C04::new(0b100), 0b100);
+    let data: [u8; 32] = core::array::from_fn(|i| i as u8);
+    eeprom.write(0xF0, &data, &mut NoDelay).unwrap();
+
+    let mut buf = [0u8; 32];
+    eeprom.read(0xF0, &mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    let device = eeprom.release();
+    assert_eq!(&device.memory()[0xF0..0x110], &data);
+    assert_eq!(device.page_write_cycles()[15..18], [1, 1, 0]);
+
+    let mut eeprom = Driver::<chip::M24M01, 131072>::new(M24M01::new(0b010), 0b010);
+    eeprom.write(0xFFFE, &[1, 2, 3, 4], &mut NoDelay).unwrap();
+    eeprom.read(0xFFFC, &mut buf[..8]).unwrap();
+    assert_eq!(buf[..8], [0xFF, 0xFF, 1, 2, 3, 4, 0xFF, 0xFF]);
+    assert_eq!(&eeprom.release().memory()[0x10000..0x10002], &[3, 4]);
+  }
+
+  #[test]
+  fn block_select_reads_are_split_at_the_block_boundary() {
+    let mut eeprom = Driver::<chip::M24C04, 512>::new(M24C04::new(0), 0);
+    let before = eeprom.i2c_mut().transactions();
+    eeprom.read(0xFC, &mut [0u8; 8]).unwrap();
+    assert_eq!(eeprom.i2c_mut().transactions() - before, 2);
+  }
+
+  #[test]
+  fn write_cycle_times_out() {
+    let device = M24C64::new(0).with_write_cycle_polls(10);
+    let mut eeprom = crate::M24C64::new(device, 0)
+      .with_write_polling(WritePolling { interval_us: 1_000, timeout_us: 5_000 });
+    assert_eq!(eeprom.write(0, &[0x12], &mut NoDelay), Err(Error::WriteTimeout));
+
+    // The data was still written, and the next access waits out the rest of the write cycle
+    let device = eeprom.i2c_mut();
+    assert!(device.is_busy());
+    assert_eq!(device.memory()[0], 0x12);
+    eeprom.set_write_polling(WritePolling::default());
+    eeprom.write(1, &[0x34], &mut NoDelay).unwrap();
+    assert_eq!(eeprom.read_byte(1), Ok(0x34));
+  }
+
+  #[test]
+  fn data_nack_is_not_a_busy_device() {
+    let mut eeprom = crate::M24C64::new(M24C64::new(0), 0);
+    let data_nack = SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data));
+
+    // The first write cycle poll
+    eeprom.i2c_mut().inject_error(1, data_nack.0);
+    assert_eq!(eeprom.write(0, &[0x12], &mut NoDelay), Err(Error::Bus(data_nack)));
+    assert_eq!(eeprom.i2c_mut().transactions(), 2);
+
+    // Nor is it while a timed out write cycle may be pending
+    eeprom.set_write_polling(WritePolling { interval_us: 1_000, timeout_us: 0 });
+    eeprom.i2c_mut().inject_error(2, ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+    assert_eq!(eeprom.wait_write_cycle(&mut NoDelay), Err(Error::WriteTimeout));
+    eeprom.i2c_mut().inject_error(3, data_nack.0);
+    assert_eq!(eeprom.write(0, &[0x34], &mut NoDelay), Err(Error::Bus(data_nack)));
+    assert_eq!(eeprom.i2c_mut().transactions(), 4);
+  }
+
+  #[test]
+  fn max_read_len_splits_reads() {
+    let mut eeprom = crate::M24C64::new(M24C64::new(0), 0).with_max_read_len(8);
+    let data: [u8; 20] = core::array::from_fn(|i| i as u8);
+    eeprom.write(0x10, &data, &mut NoDelay).unwrap();
+
+    let mut buf = [0u8; 20];
+    let before = eeprom.i2c_mut().transactions();
+    eeprom.read(0x10, &mut buf).unwrap();
+    assert_eq!(eeprom.i2c_mut().transactions() - before, 3);
+    assert_eq!(buf, data);
+
+    eeprom.set_max_read_len(None);
+    let before = eeprom.i2c_mut().transactions();
+    eeprom.read(0x10, &mut buf).unwrap();
+    assert_eq!(eeprom.i2c_mut().transactions() - before, 1);
+  }
+}
@@ -0,0 +1,285 @@
+//! Power-fail-safe A/B configuration slots.
+//!
+//! Two regions of storage are written alternately, each holding a header with a sequence number, and a checksum
+//! over the header and payload. A write only ever touches the slot that doesn't hold the newest valid copy, so if
+//! it is torn by a power loss the previous copy is still intact, and loading returns the newest valid slot.
+//!
+//! | Offset | Size | Field                 |
+//! |--------|------|-----------------------|
+//! | 0      | 4    | Sequence number (LE)  |
+//! | 4      | 2    | Payload length (LE)   |
+//! | 6      | 2    | Version (LE)          |
+//! | 8      | 4    | Checksum (LE)         |
+//! | 12     | len  | Payload               |
+
+use core::marker::PhantomData;
+
+use embedded_storage::Storage;
+
+use crate::record::{Checksum, Crc32, RecordError};
+
+/// Size of the slot header, in bytes
+pub const SLOT_HEADER_SIZE: usize = 12;
+
+/// One of the two slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+  /// The first slot
+  A,
+  /// The second slot
+  B,
+}
+
+impl Slot {
+  /// The other slot
+  pub fn other(self) -> Self {
+    match self {
+      Slot::A => Slot::B,
+      Slot::B => Slot::A,
+    }
+  }
+}
+
+/// Header of a valid slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotInfo {
+  /// Which slot the data is held in
+  pub slot: Slot,
+  /// Sequence number of the write, incremented on every store
+  pub sequence: u32,
+  /// Version given when the data was stored
+  pub version: u16,
+  /// Length of the payload, in bytes
+  pub len: usize,
+}
+
+/// Manages a pair of A/B slots on top of a [`Storage`], with the checksum algorithm `K`.
+///
+/// # Example
+/// ```
+/// use grapple_m24c64::{slots::DoubleBuffer, storage::EepromStorage, M24C64};
+/// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::record::RecordError<grapple_m24c64::Error<I2C::Error>>> {
+///
+/// // Two 256 byte slots, at 0x000 and 0x100
+/// let mut config = DoubleBuffer::<_>::new(EepromStorage::new(M24C64::new(i2c, 0), delay), 0x000, 0x100, 256);
+///
+/// let mut buf = [0u8; 64];
+/// match config.load(&mut buf) {
+///   Ok(info) => { /* use &buf[..info.len] */ },
+///   Err(grapple_m24c64::record::RecordError::Corrupted) => { /* nothing stored yet */ },
+///   Err(e) => return Err(e),
+/// }
+///
+/// config.store(1, b"new config")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DoubleBuffer<S, K = Crc32> {
+  storage: S,
+  /// Start address of each slot
+  addresses: [u32; 2],
+  /// Size of each slot, including the header
+  size: usize,
+  /// Newest valid slot, if known
+  newest: Option<SlotInfo>,
+  /// Whether the slots have been scanned since creation
+  scanned: bool,
+  _checksum: PhantomData<K>
+}
+
+impl<S, K> DoubleBuffer<S, K> {
+  /// Manage two slots of `size` bytes (including the header), starting at `a` and `b`.
+  ///
+  /// # Panics
+  /// Panics if the slots overlap, or are too small to hold a header.
+  pub fn new(storage: S, a: u32, b: u32, size: usize) -> Self {
+    assert!(size > SLOT_HEADER_SIZE, "slots must be larger than their header");
+    assert!(
+      (a as usize).saturating_add(size) <= b as usize || (b as usize).saturating_add(size) <= a as usize,
+      "slots must not overlap"
+    );
+    Self { storage, addresses: [a, b], size, newest: None, scanned: false, _checksum: PhantomData }
+  }
+
+  /// Largest payload a slot can hold
+  pub fn max_payload_len(&self) -> usize {
+    (self.size - SLOT_HEADER_SIZE).min(u16::MAX as usize)
+  }
+
+  /// Release the underlying storage
+  pub fn release(self) -> S {
+    self.storage
+  }
+
+  fn address(&self, slot: Slot) -> u32 {
+    match slot {
+      Slot::A => self.addresses[0],
+      Slot::B => self.addresses[1],
+    }
+  }
+}
+
+/// Whether sequence number `a` was written after `b`, allowing for wrap-around
+fn is_newer(a: u32, b: u32) -> bool {
+  (a.wrapping_sub(b) as i32) > 0
+}
+
+impl<S: Storage, K: Checksum> DoubleBuffer<S, K> {
+  /// Read and verify the slot's header and payload, without keeping the payload
+  fn verify(&mut self, slot: Slot) -> Result<Option<SlotInfo>, RecordError<S::Error>> {
+    let address = self.address(slot);
+    let mut header = [0u8; SLOT_HEADER_SIZE];
+    self.storage.read(address, &mut header).map_err(RecordError::Storage)?;
+
+    let sequence = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+    let version = u16::from_le_bytes([header[6], header[7]]);
+    let checksum = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if len > self.max_payload_len() {
+      return Ok(None);
+    }
+
+    // Stream the payload through the checksum in small chunks, so no buffer the size of the slot is needed
+    let mut state = K::begin();
+    K::update(&mut state, &header[0..8]);
+    let mut chunk = [0u8; 32];
+    let mut i = 0;
+    while i < len {
+      let n = (len - i).min(chunk.len());
+      self.storage.read(address + (SLOT_HEADER_SIZE + i) as u32, &mut chunk[..n]).map_err(RecordError::Storage)?;
+      K::update(&mut state, &chunk[..n]);
+      i += n;
+    }
+
+    Ok((K::finish(state) == checksum).then_some(SlotInfo { slot, sequence, version, len }))
+  }
+
+  /// Find the newest valid slot, if any. The result is cached until the next [`DoubleBuffer::store`].
+  pub fn scan(&mut self) -> Result<Option<SlotInfo>, RecordError<S::Error>> {
+    if !self.scanned {
+      let a = self.verify(Slot::A)?;
+      let b = self.verify(Slot::B)?;
+      self.newest = match (a, b) {
+        (Some(a), Some(b)) => Some(if is_newer(b.sequence, a.sequence) { b } else { a }),
+        (a, b) => a.or(b),
+      };
+      self.scanned = true;
+    }
+    Ok(self.newest)
+  }
+
+  /// Load the payload of the newest valid slot into `buf`. The payload occupies `buf[..info.len]`.
+  /// Returns [`RecordError::Corrupted`] if neither slot holds valid data.
+  pub fn load(&mut self, buf: &mut [u8]) -> Result<SlotInfo, RecordError<S::Error>> {
+    let info = self.scan()?.ok_or(RecordError::Corrupted)?;
+    if info.len > buf.len() {
+      return Err(RecordError::BufferTooSmall { required: info.len });
+    }
+    let address = self.address(info.slot) + SLOT_HEADER_SIZE as u32;
+    self.storage.read(address, &mut buf[..info.len]).map_err(RecordError::Storage)?;
+    Ok(info)
+  }
+
+  /// Store `payload` with the given `version` into the slot not holding the newest valid data, with the next
+  /// sequence number. The previous copy is left intact until a later store.
+  pub fn store(&mut self, version: u16, payload: &[u8]) -> Result<SlotInfo, RecordError<S::Error>> {
+    if payload.len() > self.max_payload_len() {
+      return Err(RecordError::TooLarge);
+    }
+
+    let (slot, sequence) = match self.scan()? {
+      Some(newest) => (newest.slot.other(), newest.sequence.wrapping_add(1)),
+      None => (Slot::A, 0),
+    };
+
+    let mut header = [0u8; SLOT_HEADER_SIZE];
+    header[0..4].copy_from_slice(&sequence.to_le_bytes());
+    header[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    header[6..8].copy_from_slice(&version.to_le_bytes());
+    let checksum = K::checksum(&[&header[0..8], payload]);
+    header[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+    // Invalidate our cached view first, in case the write fails part way through
+    self.scanned = false;
+    let address = self.address(slot);
+    self.storage.write(address + SLOT_HEADER_SIZE as u32, payload).map_err(RecordError::Storage)?;
+    self.storage.write(address, &header).map_err(RecordError::Storage)?;
+
+    let info = SlotInfo { slot, sequence, version, len: payload.len() };
+    self.newest = Some(info);
+    self.scanned = true;
+    Ok(info)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sim::{self, fixture::{lose_power, release, SimStorage}};
+
+  fn mount(storage: SimStorage) -> DoubleBuffer<SimStorage> {
+    DoubleBuffer::new(storage, 0x000, 0x100, 0x100)
+  }
+
+  fn slots(device: sim::M24C64) -> DoubleBuffer<SimStorage> {
+    mount(sim::fixture::storage(device))
+  }
+
+  #[test]
+  fn torn_store_leaves_the_older_slot_loadable() {
+    // The payload (0x0C..0x34) takes two page writes, and the header a third
+    for writes in 0..3 {
+      let mut slots = slots(sim::M24C64::new(0).with_seed(writes as u64));
+      slots.store(1, b"first").unwrap();
+      slots.store(2, b"second").unwrap();
+
+      let device = release(slots.release());
+      let (mut slots, _) = lose_power(device, writes, mount, DoubleBuffer::release, |slots| {
+        assert!(slots.store(3, &[0x33; 40]).is_err());
+      });
+      let mut buf = [0u8; 64];
+      let info = slots.load(&mut buf).unwrap();
+      assert_eq!((info.slot, info.sequence, info.version), (Slot::B, 1, 2), "torn after {} writes", writes);
+      assert_eq!(&buf[..info.len], b"second");
+
+      // The next store goes back into the torn slot
+      assert_eq!(slots.store(4, b"fourth").map(|info| (info.slot, info.sequence)), Ok((Slot::A, 2)));
+    }
+  }
+
+  #[test]
+  fn sequence_wraps_around() {
+    assert!(is_newer(0, u32::MAX));
+    assert!(!is_newer(u32::MAX, 0));
+    assert!(is_newer(u32::MAX, u32::MAX - 1));
+    assert!(!is_newer(5, 5));
+
+    let mut slots = slots(sim::M24C64::new(0));
+    slots.store(1, b"old").unwrap();
+    slots.newest = slots.newest.map(|info| SlotInfo { sequence: u32::MAX - 1, ..info });
+    assert_eq!(slots.store(2, b"max").map(|info| (info.slot, info.sequence)), Ok((Slot::B, u32::MAX)));
+    assert_eq!(slots.store(3, b"wrapped").map(|info| (info.slot, info.sequence)), Ok((Slot::A, 0)));
+
+    let mut slots = self::slots(release(slots.release()));
+    let mut buf = [0u8; 16];
+    let info = slots.load(&mut buf).unwrap();
+    assert_eq!((info.slot, info.sequence), (Slot::A, 0));
+    assert_eq!(&buf[..info.len], b"wrapped");
+  }
+
+  #[test]
+  fn flipped_bit_falls_back_to_the_other_slot() {
+    let mut slots = slots(sim::M24C64::new(0));
+    slots.store(1, b"first").unwrap();
+    slots.store(2, b"second").unwrap();
+
+    let mut device = release(slots.release());
+    device.flip_bit(0x100 + SLOT_HEADER_SIZE, 7);
+    let mut slots = self::slots(device);
+
+    let mut buf = [0u8; 16];
+    let info = slots.load(&mut buf).unwrap();
+    assert_eq!((info.slot, info.sequence, info.version), (Slot::A, 0, 1));
+    assert_eq!(&buf[..info.len], b"first");
+  }
+}
@@ -0,0 +1,200 @@
+//! [`embedded-storage`](embedded_storage) trait implementations.
+//!
+//! The storage traits don't take a delay for the write cycle, so the driver is paired with one in [`EepromStorage`].
+//! EEPROM cells can be rewritten freely, so the NOR flash traits are implemented with a write size of a single
+//! byte, and an erase size of a single page (erasing sets the page to `0xFF`).
+
+use embedded_hal::delay::DelayNs;
+use embedded_storage::{nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash}, ReadStorage, Storage};
+
+use crate::{EepromDevice, Error};
+
+/// Value of an erased byte
+const ERASED: [u8; 256] = [0xFF; 256];
+
+/// An EEPROM paired with the delay used to wait out its write cycle, implementing the `embedded-storage` traits for
+/// any [`EepromDevice`] (and the `embedded-storage-async` traits for the async driver).
+///
+/// # Example
+/// ```
+/// use embedded_storage::{ReadStorage, Storage};
+/// use grapple_m24c64::{storage::EepromStorage, M24C64};
+/// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::Error<I2C::Error>> {
+///
+/// let mut storage = EepromStorage::new(M24C64::new(i2c, 0), delay);
+/// storage.write(0x100, b"hello")?;
+///
+/// let mut buf = [0u8; 5];
+/// storage.read(0x100, &mut buf)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EepromStorage<E, D> {
+  eeprom: E,
+  delay: D
+}
+
+impl<E, D> EepromStorage<E, D> {
+  /// Pair an EEPROM with a delay
+  pub fn new(eeprom: E, delay: D) -> Self {
+    Self { eeprom, delay }
+  }
+
+  /// Get a reference to the underlying EEPROM driver
+  pub fn eeprom(&mut self) -> &mut E {
+    &mut self.eeprom
+  }
+
+  /// Release the EEPROM driver and delay
+  pub fn release(self) -> (E, D) {
+    (self.eeprom, self.delay)
+  }
+}
+
+/// Make sure an erase covers whole pages within a memory array of `capacity` bytes
+fn check_erase<E>(capacity: usize, page_size: usize, from: u32, to: u32) -> Result<(), Error<E>> {
+  if from > to || to as usize > capacity {
+    return Err(Error::AddressOutOfRange);
+  }
+  if !(from as usize).is_multiple_of(page_size) || !(to as usize).is_multiple_of(page_size) {
+    return Err(Error::NotAligned);
+  }
+  Ok(())
+}
+
+impl<E: EepromDevice, D: DelayNs> EepromStorage<E, D> {
+  fn erase_range(&mut self, from: u32, to: u32) -> Result<(), Error<E::BusError>> {
+    check_erase(E::CAPACITY, E::PAGE_SIZE, from, to)?;
+
+    let mut i = from as usize;
+    while i < to as usize {
+      let len = (to as usize - i).min(ERASED.len());
+      self.eeprom.write(i, &ERASED[..len], &mut self.delay)?;
+      i += len;
+    }
+    Ok(())
+  }
+}
+
+impl<E: EepromDevice, D: DelayNs> ReadStorage for EepromStorage<E, D> {
+  type Error = Error<E::BusError>;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    self.eeprom.read(offset as usize, bytes)
+  }
+
+  fn capacity(&self) -> usize {
+    E::CAPACITY
+  }
+}
+
+impl<E: EepromDevice, D: DelayNs> Storage for EepromStorage<E, D> {
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    self.eeprom.write(offset as usize, bytes, &mut self.delay)
+  }
+}
+
+impl<E: EepromDevice, D: DelayNs> ErrorType for EepromStorage<E, D> {
+  type Error = Error<E::BusError>;
+}
+
+impl<E: EepromDevice, D: DelayNs> ReadNorFlash for EepromStorage<E, D> {
+  const READ_SIZE: usize = 1;
+
+  fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+    self.eeprom.read(offset as usize, bytes)
+  }
+
+  fn capacity(&self) -> usize {
+    E::CAPACITY
+  }
+}
+
+impl<E: EepromDevice, D: DelayNs> NorFlash for EepromStorage<E, D> {
+  const WRITE_SIZE: usize = 1;
+  const ERASE_SIZE: usize = E::PAGE_SIZE;
+
+  fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+    self.erase_range(from, to)
+  }
+
+  fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+    self.eeprom.write(offset as usize, bytes, &mut self.delay)
+  }
+}
+
+impl<E: EepromDevice, D: DelayNs> MultiwriteNorFlash for EepromStorage<E, D> {}
+
+#[cfg(feature = "async")]
+mod asynch {
+  use embedded_hal::digital::OutputPin;
+  use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+  use embedded_storage::nor_flash::ErrorType;
+  use embedded_storage_async::{nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash}, ReadStorage, Storage};
+
+  use super::{check_erase, EepromStorage, ERASED};
+  use crate::{asynch::M24Cxx, Chip, Error};
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> EepromStorage<M24Cxx<I2C, C, WC>, D> {
+    async fn erase_range(&mut self, from: u32, to: u32) -> Result<(), Error<I2C::Error>> {
+      check_erase(C::CAPACITY, C::PAGE_SIZE, from, to)?;
+
+      let mut i = from as usize;
+      while i < to as usize {
+        let len = (to as usize - i).min(ERASED.len());
+        self.eeprom.write(i, &ERASED[..len], &mut self.delay).await?;
+        i += len;
+      }
+      Ok(())
+    }
+  }
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> ReadStorage for EepromStorage<M24Cxx<I2C, C, WC>, D> {
+    type Error = Error<I2C::Error>;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+      self.eeprom.read(offset as usize, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+      C::CAPACITY
+    }
+  }
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> Storage for EepromStorage<M24Cxx<I2C, C, WC>, D> {
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+      self.eeprom.write(offset as usize, bytes, &mut self.delay).await
+    }
+  }
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> ErrorType for EepromStorage<M24Cxx<I2C, C, WC>, D> {
+    type Error = Error<I2C::Error>;
+  }
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> ReadNorFlash for EepromStorage<M24Cxx<I2C, C, WC>, D> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+      self.eeprom.read(offset as usize, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+      C::CAPACITY
+    }
+  }
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> NorFlash for EepromStorage<M24Cxx<I2C, C, WC>, D> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = C::PAGE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+      self.erase_range(from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+      self.eeprom.write(offset as usize, bytes, &mut self.delay).await
+    }
+  }
+
+  impl<I2C: I2c, C: Chip, WC: OutputPin, D: DelayNs> MultiwriteNorFlash for EepromStorage<M24Cxx<I2C, C, WC>, D> {}
+}
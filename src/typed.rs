@@ -0,0 +1,162 @@
+//! Typed storage of Rust values in the EEPROM.
+//!
+//! With the `bytemuck` feature, plain-old-data types ([`bytemuck::Pod`]) are stored as their raw bytes.
+//! With the `serde` feature, any [`serde::Serialize`] type is encoded with [`postcard`], through a caller-provided
+//! scratch buffer.
+//!
+//! In both cases the encoded size is checked against the remaining capacity before anything is written.
+
+use embedded_hal::{delay::DelayNs, digital::OutputPin, i2c::I2c};
+
+use crate::{Chip, Error, M24Cxx};
+
+impl<I2C, C, WC> M24Cxx<I2C, C, WC>
+where
+  I2C: I2c,
+  C: Chip,
+  WC: OutputPin
+{
+  /// Store a plain-old-data value at `address`, as its raw bytes.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::M24C64;
+  ///
+  /// #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+  /// #[repr(C)]
+  /// struct Calibration { offset: i32, gain: f32 }
+  /// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: &mut impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::Error<I2C::Error>> {
+  ///
+  /// let mut eeprom = M24C64::new(i2c, 0);
+  /// eeprom.store(0x100, &Calibration { offset: -12, gain: 1.05 }, delay)?;
+  /// let calibration: Calibration = eeprom.load(0x100)?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "bytemuck")]
+  pub fn store<T: bytemuck::Pod>(&mut self, address: usize, value: &T, delay: &mut dyn DelayNs) -> Result<(), Error<I2C::Error>> {
+    self.write(address, bytemuck::bytes_of(value), delay)
+  }
+
+  /// Load a plain-old-data value stored at `address` with [`M24Cxx::store`]
+  #[cfg(feature = "bytemuck")]
+  pub fn load<T: bytemuck::Pod>(&mut self, address: usize) -> Result<T, Error<I2C::Error>> {
+    let mut value = T::zeroed();
+    self.read(address, bytemuck::bytes_of_mut(&mut value))?;
+    Ok(value)
+  }
+
+  /// Serialize `value` with postcard into `buf`, and store it at `address`. Returns the number of bytes written.
+  /// `buf` must be large enough to hold the encoded value.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::M24C64;
+  ///
+  /// #[derive(serde::Serialize, serde::Deserialize)]
+  /// struct Config { baud: u32, parity: bool, name: [u8; 8] }
+  /// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, delay: &mut impl embedded_hal::delay::DelayNs, config: Config) -> Result<(), grapple_m24c64::Error<I2C::Error>> {
+  ///
+  /// let mut eeprom = M24C64::new(i2c, 0);
+  /// let mut buf = [0u8; 32];
+  /// eeprom.store_serde(0x200, &config, &mut buf, delay)?;
+  /// let config: Config = eeprom.load_serde(0x200, &mut buf)?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "serde")]
+  pub fn store_serde<T: serde::Serialize>(&mut self, address: usize, value: &T, buf: &mut [u8], delay: &mut dyn DelayNs) -> Result<usize, Error<I2C::Error>> {
+    let encoded = postcard::to_slice(value, buf).map_err(|_| Error::Serialization)?;
+    self.write(address, encoded, delay)?;
+    Ok(encoded.len())
+  }
+
+  /// Load a value stored at `address` with [`M24Cxx::store_serde`], using `buf` as scratch space.
+  /// Up to `buf.len()` bytes are read, so `buf` must be at least as large as the encoded value.
+  #[cfg(feature = "serde")]
+  pub fn load_serde<T: serde::de::DeserializeOwned>(&mut self, address: usize, buf: &mut [u8]) -> Result<T, Error<I2C::Error>> {
+    let buf = Self::clamp_to_capacity(address, buf)?;
+    self.read(address, buf)?;
+    postcard::from_bytes(buf).map_err(|_| Error::Serialization)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<I2C, C: Chip, WC> M24Cxx<I2C, C, WC> {
+  /// Shrink `buf` so that reading it from `address` doesn't run past the end of the memory array
+  pub(crate) fn clamp_to_capacity<E>(address: usize, buf: &mut [u8]) -> Result<&mut [u8], Error<E>> {
+    let remaining = C::CAPACITY.checked_sub(address).ok_or(Error::AddressOutOfRange)?;
+    let len = buf.len().min(remaining);
+    Ok(&mut buf[..len])
+  }
+}
+
+#[cfg(all(test, any(feature = "bytemuck", feature = "serde")))]
+mod tests {
+  use crate::{device::NoDelay, sim, Error, M24C64};
+
+  const CAPACITY: usize = M24C64::<sim::M24C64>::CAPACITY;
+
+  #[cfg(feature = "bytemuck")]
+  #[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+  #[repr(C)]
+  struct Calibration { offset: i32, gain: f32 }
+
+  #[cfg(feature = "serde")]
+  #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+  struct Config { baud: u32, parity: bool, name: [u8; 8] }
+
+  #[cfg(feature = "bytemuck")]
+  #[test]
+  fn pod_round_trip() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    let calibration = Calibration { offset: -12, gain: 1.05 };
+
+    // Across a page boundary, and right at the end of memory
+    for address in [0x11C, CAPACITY - 8] {
+      eeprom.store(address, &calibration, &mut NoDelay).unwrap();
+      assert_eq!(eeprom.load::<Calibration>(address).unwrap(), calibration);
+    }
+  }
+
+  #[cfg(feature = "bytemuck")]
+  #[test]
+  fn pod_past_the_end_is_out_of_range() {
+    let mut device = sim::M24C64::new(0);
+    let mut eeprom = M24C64::new(&mut device, 0);
+    let calibration = Calibration { offset: -12, gain: 1.05 };
+
+    assert_eq!(eeprom.store(CAPACITY - 4, &calibration, &mut NoDelay), Err(Error::AddressOutOfRange));
+    assert_eq!(eeprom.load::<Calibration>(CAPACITY - 4), Err(Error::AddressOutOfRange));
+    assert_eq!(eeprom.load::<Calibration>(CAPACITY), Err(Error::AddressOutOfRange));
+    assert_eq!(device.transactions(), 0);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn serde_round_trip() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    let config = Config { baud: 115_200, parity: true, name: *b"uart0\0\0\0" };
+    let mut buf = [0u8; 32];
+
+    let len = eeprom.store_serde(0x200, &config, &mut buf, &mut NoDelay).unwrap();
+    assert!(len < buf.len());
+    assert_eq!(eeprom.load_serde::<Config>(0x200, &mut buf).unwrap(), config);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn load_serde_trims_its_buffer_at_the_end_of_memory() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0);
+    let config = Config { baud: 9600, parity: false, name: *b"console\0" };
+    let mut buf = [0u8; 32];
+
+    // The encoded value fits in the last bytes of memory, but the scratch buffer doesn't
+    let len = eeprom.store_serde(0, &config, &mut buf, &mut NoDelay).unwrap();
+    let address = CAPACITY - len;
+    eeprom.store_serde(address, &config, &mut buf, &mut NoDelay).unwrap();
+    assert_eq!(eeprom.load_serde::<Config>(address, &mut buf).unwrap(), config);
+
+    assert_eq!(eeprom.load_serde::<Config>(CAPACITY + 1, &mut buf), Err(Error::AddressOutOfRange));
+  }
+}
@@ -0,0 +1,169 @@
+use core::convert::Infallible;
+
+use embedded_hal::digital::{self, Error as _, OutputPin};
+
+use crate::{Chip, Error, M24Cxx};
+
+/// Placeholder for drivers without a Write Control pin. Writes are always enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NoWriteControl;
+
+impl digital::ErrorType for NoWriteControl {
+  type Error = Infallible;
+}
+
+impl OutputPin for NoWriteControl {
+  fn set_low(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+
+  fn set_high(&mut self) -> Result<(), Self::Error> {
+    Ok(())
+  }
+}
+
+impl<I2C, C: Chip> M24Cxx<I2C, C, NoWriteControl> {
+  /// Attach the Write Control (WC) pin. Writes are inhibited while WC is high, so the driver pulls it low only
+  /// for the duration of each write, and drives it high again afterwards.
+  ///
+  /// The pin is expected to already be high (write protected) when attached.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::M24C64;
+  /// # fn example(i2c: impl embedded_hal::i2c::I2c, wc: impl embedded_hal::digital::OutputPin) {
+  ///
+  /// let eeprom = M24C64::new(i2c, 0).with_write_control(wc);
+  /// # }
+  /// ```
+  pub fn with_write_control<WC: OutputPin>(self, wc: WC) -> M24Cxx<I2C, C, WC> {
+    M24Cxx {
+      i2c: self.i2c, e_addr: self.e_addr,
+      wc, write_enabled: false,
+      polling: self.polling, write_pending: self.write_pending, max_read_len: self.max_read_len, _chip: self._chip
+    }
+  }
+}
+
+impl<I2C, C: Chip, WC: OutputPin> M24Cxx<I2C, C, WC> {
+  /// Release the Write Control pin (pull it low), run `f`, and drive it high again afterwards, even if `f` fails.
+  /// Writes made inside `f` don't toggle the pin themselves, so this can be used to batch several writes.
+  ///
+  /// # Example
+  /// ```
+  /// use grapple_m24c64::M24C64;
+  /// # fn example<I2C: embedded_hal::i2c::I2c>(i2c: I2C, wc: impl embedded_hal::digital::OutputPin, delay: &mut impl embedded_hal::delay::DelayNs) -> Result<(), grapple_m24c64::Error<I2C::Error>> {
+  ///
+  /// let mut eeprom = M24C64::new(i2c, 0).with_write_control(wc);
+  /// eeprom.with_write_enabled(|eeprom| {
+  ///   eeprom.write(0x00, &[0x01, 0x02], delay)?;
+  ///   eeprom.write(0x40, &[0x03, 0x04], delay)
+  /// })?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn with_write_enabled<T, E>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error<E>>) -> Result<T, Error<E>> {
+    if self.write_enabled {
+      return f(self);
+    }
+
+    self.enable_writes()?;
+    let result = f(self);
+    let restore = self.disable_writes();
+
+    let value = result?;
+    restore?;
+    Ok(value)
+  }
+
+  /// Pull the Write Control pin low
+  pub(crate) fn enable_writes<E>(&mut self) -> Result<(), Error<E>> {
+    self.wc.set_low().map_err(|e| Error::WriteControl(e.kind()))?;
+    self.write_enabled = true;
+    Ok(())
+  }
+
+  /// Drive the Write Control pin high again
+  pub(crate) fn disable_writes<E>(&mut self) -> Result<(), Error<E>> {
+    self.write_enabled = false;
+    self.wc.set_high().map_err(|e| Error::WriteControl(e.kind()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use embedded_hal::i2c::ErrorKind;
+
+  use super::*;
+  use crate::{sim::{self, NoDelay, SimError}, M24C64};
+
+  /// A Write Control pin that records every level it is driven to (`true` for high)
+  #[derive(Default)]
+  struct RecordingPin(Vec<bool>);
+
+  impl digital::ErrorType for RecordingPin {
+    type Error = Infallible;
+  }
+
+  impl OutputPin for RecordingPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+      self.0.push(false);
+      Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+      self.0.push(true);
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn pin_is_released_only_while_writing() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0).with_write_control(RecordingPin::default());
+    eeprom.write(0x00, &[0x01; 40], &mut NoDelay).unwrap();
+    eeprom.read(0x00, &mut [0u8; 40]).unwrap();
+
+    let (_, pin) = eeprom.release_parts();
+    assert_eq!(pin.0, [false, true]);
+  }
+
+  #[test]
+  fn pin_is_driven_high_after_a_failed_write() {
+    let mut device = sim::M24C64::new(0);
+    device.inject_error(0, ErrorKind::ArbitrationLoss);
+    let mut eeprom = M24C64::new(device, 0).with_write_control(RecordingPin::default());
+    assert_eq!(eeprom.write(0x00, &[0x01; 4], &mut NoDelay), Err(Error::Bus(SimError(ErrorKind::ArbitrationLoss))));
+
+    let (device, pin) = eeprom.release_parts();
+    assert_eq!(pin.0, [false, true]);
+    assert_eq!(&device.memory()[..4], &[0xFF; 4]);
+  }
+
+  #[test]
+  fn nested_writes_share_one_release() {
+    let mut eeprom = M24C64::new(sim::M24C64::new(0), 0).with_write_control(RecordingPin::default());
+    eeprom.with_write_enabled(|eeprom| {
+      eeprom.write(0x00, &[0x01, 0x02], &mut NoDelay)?;
+      eeprom.write_if_changed(0x40, &[0x03, 0x04], &mut NoDelay)?;
+      eeprom.with_write_enabled(|eeprom| eeprom.write(0x80, &[0x05], &mut NoDelay))
+    }).unwrap();
+
+    let (device, pin) = eeprom.release_parts();
+    assert_eq!(pin.0, [false, true]);
+    assert_eq!(&device.memory()[0x40..0x42], &[0x03, 0x04]);
+    assert_eq!(device.memory()[0x80], 0x05);
+  }
+
+  #[test]
+  fn error_inside_a_batch_still_drives_the_pin_high() {
+    let mut device = sim::M24C64::new(0);
+    device.inject_error(1, ErrorKind::Bus);
+    let mut eeprom = M24C64::new(device, 0).with_write_control(RecordingPin::default());
+    let result = eeprom.with_write_enabled(|eeprom| {
+      eeprom.write(0x00, &[0x01], &mut NoDelay)?;
+      eeprom.write(0x40, &[0x02], &mut NoDelay)
+    });
+    assert_eq!(result, Err(Error::Bus(SimError(ErrorKind::Bus))));
+    assert_eq!(eeprom.release_parts().1 .0, [false, true]);
+  }
+}